@@ -1,9 +1,12 @@
-use anyhow::{anyhow, Result};
-use checkmate::{JobRunner, Task, TaskResult};
+use checkmate::{Destination, JobRunner, JobThread, Progress, Script, Status, TaskResult};
+use crossterm::event::KeyCode;
+use std::collections::BTreeSet;
 use std::process::Output;
+use std::time::{Duration, Instant};
+use std::sync::atomic::Ordering;
 use tui::{
     backend::Backend,
-    layout::{Alignment, Constraint, Direction, Layout},
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans},
     widgets::{Block, BorderType, Borders, Cell, Paragraph, Row, Table, TableState, Wrap},
@@ -13,8 +16,31 @@ use tui::{
 pub struct State {
     pub job_table: TableState,
     pub draw_mode: DrawMode,
+    /// Only show tasks running on this destination
+    pub destination_filter: Option<Destination>,
+    /// Only show tasks with this tag
+    pub tag_filter: Option<String>,
+    /// Which output the task view shows
+    pub stream: Stream,
+    /// Lines scrolled down in the task view
+    pub scroll: u16,
+    /// Frame of the in progress spinner, advanced every tick
+    pub spinner: usize,
+    /// Show whole scripts in the task view rather than their first line
+    pub expand_scripts: bool,
+    /// Only tasks whose name contains this are shown in the job table
+    pub search: String,
+    /// Typed keys go to `search` rather than being commands
+    pub searching: bool,
+    /// Shown in place of the help for a few seconds, e.g. after copying
+    pub message: Option<(String, Instant)>,
 }
 
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+/// How long a message stays up in place of the help
+const MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
 impl Default for State {
     fn default() -> Self {
         let mut job_table = TableState::default();
@@ -23,16 +49,32 @@ impl Default for State {
         Self {
             job_table,
             draw_mode: DrawMode::Job,
+            destination_filter: None,
+            tag_filter: None,
+            stream: Stream::Stdout,
+            scroll: 0,
+            spinner: 0,
+            expand_scripts: false,
+            search: String::new(),
+            searching: false,
+            message: None,
         }
     }
 }
 
 impl State {
+    pub fn tick(&mut self) {
+        self.spinner = (self.spinner + 1) % SPINNER.len();
+        if self.message.as_ref().is_some_and(|(_, at)| at.elapsed() >= MESSAGE_DURATION) {
+            self.message = None;
+        }
+    }
+
     pub fn up_key(&mut self) {
         self.job_table.select(
             self.job_table
                 .selected()
-                .map(|x| (x.saturating_sub(1)).max(0)),
+                .map(|x| x.saturating_sub(1)),
         );
     }
 
@@ -43,12 +85,176 @@ impl State {
 
     pub fn enter_key(&mut self) {
         self.draw_mode = DrawMode::Task;
+        self.scroll = 0;
     }
 
     pub fn back_key(&mut self) {
         self.draw_mode = DrawMode::Job;
     }
 
+    /// Switch the task view between stdout and stderr
+    pub fn tab_key(&mut self) {
+        self.stream = match self.stream {
+            Stream::Stdout => Stream::Stderr,
+            Stream::Stderr => Stream::Stdout,
+        };
+        self.scroll = 0;
+    }
+
+    pub fn expand_key(&mut self) {
+        self.expand_scripts = !self.expand_scripts;
+    }
+
+    pub fn page_up_key(&mut self) {
+        self.scroll = self.scroll.saturating_sub(10);
+    }
+
+    pub fn page_down_key(&mut self) {
+        self.scroll = self.scroll.saturating_add(10);
+    }
+
+    /// Copy the selected task's one line status to the clipboard, or in the task view
+    /// the output shown
+    pub fn copy_key(&mut self, runner: &JobRunner) {
+        let Some(i) = self.selected_thread(runner) else {
+            return;
+        };
+        let text = match self.draw_mode {
+            DrawMode::Job => runner.threads[i].status_line(),
+            DrawMode::Task => String::from_utf8_lossy(&self.shown_output(&runner.threads[i])).into_owned(),
+        };
+        let message = match copy(&text) {
+            Ok(()) => format!("Copied {} bytes", text.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::Unsupported => e.to_string(),
+            Err(e) => format!("Copy failed: {e}"),
+        };
+        self.message = Some((message, Instant::now()));
+    }
+
+    /// Every script's output on the shown stream, so far if the task is still running
+    fn shown_output(&self, job_thread: &JobThread) -> Vec<u8> {
+        match &*job_thread.thread.borrow() {
+            Ok(result) => result
+                .outputs()
+                .iter()
+                .filter_map(|output| output.as_ref().ok())
+                .flat_map(|output| self.stream.of(output).to_vec())
+                .collect(),
+            Err(_) => job_thread.progress.iter().flat_map(|p| self.stream.live(p)).collect(),
+        }
+    }
+
+    /// Toggle showing only tasks on the selected task's destination
+    pub fn destination_key(&mut self, runner: &JobRunner) {
+        if !matches!(self.draw_mode, DrawMode::Job) {
+            return;
+        }
+
+        let selected = self.selected_thread(runner);
+        self.destination_filter = match self.destination_filter {
+            Some(_) => None,
+            None => selected
+                .and_then(|i| runner.threads[i].task.scripts().first())
+                .map(|s| s.destination.clone()),
+        };
+        self.reselect(runner, selected);
+    }
+
+    /// Show only tasks with the next of the job's tags, after the last one show every task
+    pub fn tag_key(&mut self, runner: &JobRunner) {
+        if !matches!(self.draw_mode, DrawMode::Job) {
+            return;
+        }
+
+        let selected = self.selected_thread(runner);
+        let tags: BTreeSet<&String> = runner.threads.iter().flat_map(|jr| jr.task.tags()).collect();
+        self.tag_filter = match &self.tag_filter {
+            Some(current) => tags.into_iter().find(|tag| *tag > current),
+            None => tags.first().copied(),
+        }
+        .map(|tag| tag.to_string());
+        self.reselect(runner, selected);
+    }
+
+    /// Cancel the selected task, in either view
+    pub fn cancel_key(&self, runner: &JobRunner) {
+        if let Some(i) = self.selected_thread(runner) {
+            runner.threads[i].cancel.cancel();
+        }
+    }
+
+    /// Back to the top of the job view once the failed tasks are rerun
+    pub fn rerun_key(&mut self) {
+        self.draw_mode = DrawMode::Job;
+        self.job_table.select(Some(0));
+        self.scroll = 0;
+    }
+
+    /// Start typing a search in the job view
+    pub fn search_key(&mut self) {
+        if matches!(self.draw_mode, DrawMode::Job) {
+            self.searching = true;
+        }
+    }
+
+    /// Handle a key while searching. Enter keeps the filter, Esc clears it.
+    pub fn search_input(&mut self, code: KeyCode, runner: &JobRunner) {
+        let selected = self.selected_thread(runner);
+        match code {
+            KeyCode::Char(c) => self.search.push(c),
+            KeyCode::Backspace => {
+                self.search.pop();
+            }
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.clear_search();
+            }
+            _ => return,
+        }
+        self.reselect(runner, selected);
+    }
+
+    /// Drop the search filter, true if there was one
+    pub fn clear_search(&mut self) -> bool {
+        self.searching = false;
+        !std::mem::take(&mut self.search).is_empty()
+    }
+
+    /// Keep `selected` selected if it's still shown, otherwise select the first row
+    fn reselect(&mut self, runner: &JobRunner, selected: Option<usize>) {
+        let row = selected.and_then(|i| self.visible(runner).iter().position(|v| *v == i));
+        self.job_table.select(row.or(Some(0)));
+    }
+
+    /// Indices into `runner.threads` of the rows shown in the job table
+    pub fn visible(&self, runner: &JobRunner) -> Vec<usize> {
+        runner
+            .threads
+            .iter()
+            .enumerate()
+            .filter(|(_, jr)| match &self.destination_filter {
+                Some(d) => jr.task.scripts().iter().any(|s| s.destination == *d),
+                None => true,
+            })
+            .filter(|(_, jr)| match &self.tag_filter {
+                Some(tag) => jr.task.tags().any(|t| t == tag),
+                None => true,
+            })
+            .filter(|(_, jr)| {
+                let name = jr.task.name().to_lowercase();
+                name.contains(&self.search.to_lowercase())
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Index into `runner.threads` of the selected row
+    pub fn selected_thread(&self, runner: &JobRunner) -> Option<usize> {
+        self.job_table
+            .selected()
+            .and_then(|row| self.visible(runner).get(row).copied())
+    }
+
     pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, runner: &JobRunner) {
         match self.draw_mode {
             DrawMode::Job => self.draw_job(f, runner),
@@ -57,42 +263,33 @@ impl State {
     }
 
     fn draw_job<B: Backend>(&mut self, f: &mut Frame<B>, runner: &JobRunner) {
-        let rows: Vec<Row> = runner
-            .threads
-            .iter()
+        let chunks = Self::chunks(f.size());
+        // Inside the borders and highlight symbol, the Task column gets 20%
+        let name_width = chunks[0].width.saturating_sub(4) as usize * 20 / 100;
+
+        let rows: Vec<Row> = self
+            .visible(runner)
+            .into_iter()
+            .map(|i| &runner.threads[i])
             .map(|jr| {
                 let (status, ty, output) = match &(*jr.thread.borrow()) {
-                    Ok(TaskResult::Script(Err(e))) => (
-                        Cell::from("Failed").style(Style::default().fg(Color::Red)),
+                    Ok(result @ TaskResult::Script(Err(e))) => (
+                        Self::status_cell(jr.task.status(result)),
                         Cell::from(format!("{}", jr.task)),
                         Cell::from(format!("{e:?}")),
                     ),
-                    Ok(TaskResult::Script(Ok(x))) => (
-                        Cell::from("Complete").style(Style::default().fg(Color::Green)),
+                    Ok(result @ TaskResult::Script(Ok(x))) => (
+                        Self::status_cell(jr.task.status(result)),
                         Cell::from(format!("{}", jr.task)),
-                        Cell::from(String::from_utf8(x.stdout.clone()).expect("Failed to make string")),
+                        Cell::from(String::from_utf8_lossy(&x.stdout).into_owned()),
                     ),
-                    Ok(TaskResult::Serial(x)) => {
-                        let errors = x.iter().fold(String::new(), |acc, x| {
-                            if let Err(e) = x {
-                                format!("{}:{}", acc, e)
-                            } else {
-                                acc
-                            }
-                        });
-
-                        let status = if errors.len() != 0 {
-                            Cell::from("Error").style(Style::default().fg(Color::Red))
-                        } else {
-                            Cell::from("Complete").style(Style::default().fg(Color::Green))
-                        };
+                    Ok(result @ (TaskResult::Serial(x) | TaskResult::Parallel(x))) => {
                         (
-                            status,
-                        Cell::from(format!("{:?}", jr.task)),
+                            Self::status_cell(jr.task.status(result)),
+                            Cell::from(format!("{}", jr.task)),
                             Cell::from(x.iter()
                                 .map(|x| match &x {
-                                    Ok(x) => String::from_utf8(x.stdout.clone())
-                                        .expect("Failed to make string"),
+                                    Ok(x) => String::from_utf8_lossy(&x.stdout).into_owned(),
                                     Err(e) => format!("{e}"),
                                 })
                                 .collect::<Vec<String>>()
@@ -100,34 +297,56 @@ impl State {
                         )
                     }
                     Err(e) => (
-                        Cell::from("In progress").style(Style::default().fg(Color::Blue)),
+                        Cell::from(self.in_progress(jr)),
                         Cell::from(format!("{}", jr.task)),
                         Cell::from(format!("{e}")),
                     ),
-                    x => (
-                        Cell::from("Unknown").style(Style::default()),
-                        Cell::from(format!("{}", jr.task)),
-                        Cell::from(format!("{:?}", x)),
-                    ),
                 };
 
-                Row::new(vec![Cell::from(jr.task.name()), status, ty, output])
+                // Keeps ticking while running, frozen once finished
+                let duration = if jr.is_queued() {
+                    String::new()
+                } else {
+                    format!("{:.2}s", jr.duration().as_secs_f64())
+                };
+
+                Row::new(vec![
+                    Cell::from(truncate(&jr.task.name(), name_width)),
+                    status,
+                    Cell::from(duration),
+                    ty,
+                    output,
+                ])
+                .style(Self::status_style(&jr.status()))
             })
             .collect();
 
         let table = Table::new(rows)
             .block(
                 Block::default()
-                    .title(format!("Job: {}", runner.job.name))
+                    .title({
+                        let mut title = format!("Job: {}", runner.job.name);
+                        if let Some(d) = &self.destination_filter {
+                            title.push_str(&format!(" - Destination: {d}"));
+                        }
+                        if let Some(tag) = &self.tag_filter {
+                            title.push_str(&format!(" - Tag: {tag}"));
+                        }
+                        if !self.search.is_empty() {
+                            title.push_str(&format!(" - Search: {}", self.search));
+                        }
+                        title
+                    })
                     .borders(Borders::ALL)
                     .border_type(BorderType::Rounded),
             )
             // .style(Style::default().fg(Color::White))
             .widths(&[
                 Constraint::Percentage(20),
-                Constraint::Percentage(6),
+                Constraint::Percentage(12),
+                Constraint::Percentage(8),
                 Constraint::Percentage(14),
-                Constraint::Percentage(60),
+                Constraint::Percentage(46),
             ])
             .highlight_style(
                 Style::default()
@@ -137,145 +356,324 @@ impl State {
             )
             .highlight_symbol("> ")
             .column_spacing(1)
-            .header(Row::new(vec!["Task", "Status", "Type", "Output"])
+            .header(Row::new(vec!["Task", "Status", "Duration", "Type", "Output"])
                 .bottom_margin(1)
                 .style(Style::default().add_modifier(Modifier::BOLD))
             );
 
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .margin(1)
-            .constraints(
-                [
-                    Constraint::Percentage(95),
-                    Constraint::Min(1),
-                ]
-                .as_ref(),
-            )
-            .split(f.size());
-
         f.render_stateful_widget(table, chunks[0], &mut self.job_table);
-        f.render_widget(Self::help(), chunks[1]);
+        f.render_widget(Self::summary(runner), chunks[1]);
+        f.render_widget(self.help(runner.control.is_paused()), chunks[2]);
     }
 
     fn draw_task<B: Backend>(&mut self, f: &mut Frame<B>, runner: &JobRunner) {
-        let thread = runner.threads[self.job_table.selected().expect("NO SELECTION")]
-            .thread
-            .borrow();
-        let (status, output) = match &(*thread) {
-            Ok(TaskResult::Script(Err(e))) => (
-                Span::styled("Failed", Style::default().fg(Color::Red)),
+        let chunks = Self::chunks(f.size());
+        let Some(index) = self.selected_thread(runner) else {
+            self.draw_mode = DrawMode::Job;
+            return self.draw_job(f, runner);
+        };
+        let job_thread = &runner.threads[index];
+        let task = &job_thread.task;
+        let thread = job_thread.thread.borrow();
+        let (status, mut output) = match &(*thread) {
+            Ok(result @ TaskResult::Script(Err(e))) => (
+                Self::status_span(task.status(result)),
                 vec![Spans::from(vec![Span::raw(format!("{e:?}"))])],
             ),
-            Ok(TaskResult::Script(Ok(x))) => (
-                Span::styled("Complete", Style::default().fg(Color::Green)),
-                vec![Spans::from(vec![Span::raw(
-                    String::from_utf8(x.stdout.clone()).expect("Failed to make string"),
-                )])],
+            Ok(result @ TaskResult::Script(Ok(x))) => (
+                Self::status_span(task.status(result)),
+                Self::marker_line(&task.scripts()[0], x)
+                    .into_iter()
+                    .chain([Spans::from(vec![Span::raw(
+                        String::from_utf8_lossy(self.stream.of(x)).into_owned(),
+                    )])])
+                    .collect(),
             ),
-            Ok(TaskResult::Serial(x)) => {
-                let errors = x.iter().fold(String::new(), |acc, x| {
-                    if let Err(e) = x {
-                        format!("{}:{}", acc, e)
-                    } else {
-                        acc
-                    }
-                });
-
-                let status = if errors.len() != 0 {
-                    Span::styled("Error", Style::default().fg(Color::Red))
-                } else {
-                    Span::styled("Complete", Style::default().fg(Color::Green))
-                };
-
+            Ok(result @ (TaskResult::Serial(x) | TaskResult::Parallel(x))) => {
                 (
-                    status.clone(),
+                    Self::status_span(task.status(result)),
                     x.iter()
                         .enumerate()
-                        .map(|(i, x)| {
-                            let task_name = if let Task::Serial(t) =
-                                &runner.job.tasks[self.job_table.selected().expect("NO SELECTION")]
-                            {
-                                t[i].name.clone()
-                            } else {
-                                "".to_string()
-                            };
+                        .flat_map(|(i, x)| {
+                            let script = &task.scripts()[i];
+                            let task_name = script.name.clone();
 
-                            let status = if x.is_err() {
-                                Span::styled("Error", Style::default().fg(Color::Red))
-                            } else {
-                                Span::styled("Complete", Style::default().fg(Color::Green))
-                            };
+                            let status = Self::status_span(script.status(x));
 
                             let output = match &x {
-                                Ok(x) => String::from_utf8(x.stdout.clone())
-                                    .expect("Failed to make string"),
+                                Ok(x) => String::from_utf8_lossy(self.stream.of(x)).into_owned(),
                                 Err(e) => format!("{e}"),
                             };
 
-                            let title_text = format!("Task[{}] {} - ", i, task_name);
-                            let title = Spans::from(vec![Span::raw(title_text.clone()), status]);
+                            // Serial steps read as numbered stages, parallel scripts by name
+                            let label = match result {
+                                TaskResult::Serial(_) => format!("step {}: {task_name}", i + 1),
+                                _ => task_name,
+                            };
+                            let title_text = match job_thread.attempts()[i] {
+                                n if n > 1 => format!("── {label} (attempt {n}) ── "),
+                                _ => format!("── {label} ── "),
+                            };
+                            let title = Spans::from(vec![
+                                Span::styled(title_text, Style::default().add_modifier(Modifier::BOLD)),
+                                status,
+                            ]);
 
                             let mut lines: Vec<Spans> = output
                                 .lines()
                                 .map(|l| Spans::from(vec![Span::raw(String::from(l))]))
                                 .collect();
+                            if let Some(markers) =
+                                x.as_ref().ok().and_then(|x| Self::marker_line(script, x))
+                            {
+                                lines.insert(0, markers);
+                            }
+                            for (j, line) in self.command_lines(script).into_iter().enumerate() {
+                                lines.insert(j, line);
+                            }
                             lines.insert(0, title);
-                            lines.push(Spans::from(vec![Span::raw("⎯".repeat(35))]));
+                            lines.push(Spans::from(vec![Span::raw("")]));
 
                             lines
                         })
-                        .flatten()
                         .collect(),
                 )
             }
-            Err(e) => (
-                Span::styled("In progress", Style::default().fg(Color::Blue)),
-                vec![Spans::from(vec![Span::raw(format!("{e}"))])],
-            ),
+            Err(_) => (self.in_progress(job_thread), self.live_lines(job_thread)),
         };
 
+        // Multi-script tasks show each command under its step header once finished
+        if task.scripts().len() == 1 || thread.is_err() {
+            let commands: Vec<Spans> = task
+                .scripts()
+                .iter()
+                .flat_map(|s| self.command_lines(s))
+                .collect();
+            output.splice(0..0, commands);
+        }
+
+        // Fit the task name between the rest of the title and the borders
+        let prefix = match job_thread.attempts()[..] {
+            [n] if n > 1 => format!("Job: {} - Task[{}] (attempt {n}): ", runner.job.name, index),
+            _ => format!("Job: {} - Task[{}]: ", runner.job.name, index),
+        };
+        let stream = match self.stream {
+            Stream::Stdout => Span::raw(" [stdout]"),
+            Stream::Stderr => Span::styled(" [stderr]", Style::default().fg(Color::Red)),
+        };
+        let streaming = match thread.is_err() && !job_thread.is_queued() {
+            true => Span::styled(" streaming…", Style::default().fg(Color::DarkGray)),
+            false => Span::raw(""),
+        };
+        let name_width = (chunks[0].width as usize).saturating_sub(
+            2 + prefix.chars().count() + 3 + status.width() + stream.width() + streaming.width(),
+        );
+        let name = task.name();
+        let short_name = truncate(&name, name_width);
+        if short_name != name {
+            output.insert(0, Spans::from(vec![Span::raw(format!("Task: {name}"))]));
+        }
+
         let paragraph = Paragraph::new(output)
             .block(
                 Block::default()
                     .title(Spans::from(vec![
-                        Span::raw(format!(
-                            "Job: {} - Task[{}]: {} - ",
-                            runner.job.name,
-                            self.job_table.selected().expect(""),
-                            runner.job.tasks[self.job_table.selected().expect("")].name()
-                        )),
+                        Span::raw(format!("{prefix}{short_name} - ")),
                         status,
+                        stream,
+                        streaming,
                     ]))
                     .borders(Borders::ALL)
                     .border_type(BorderType::Rounded),
             )
             // .style(Style::default().fg(Color::White).bg(Color::Black))
             .alignment(Alignment::Left)
-            .wrap(Wrap { trim: true });
+            .wrap(Wrap { trim: true })
+            .scroll((self.scroll, 0));
 
-        let chunks = Layout::default()
+        f.render_widget(paragraph, chunks[0]);
+        f.render_widget(Self::summary(runner), chunks[1]);
+        f.render_widget(self.help(runner.control.is_paused()), chunks[2]);
+    }
+
+    /// Main view, summary line and help
+    fn chunks(area: Rect) -> Vec<Rect> {
+        Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
             .constraints(
                 [
-                    Constraint::Percentage(95),
                     Constraint::Min(1),
+                    Constraint::Length(1),
+                    Constraint::Length(2),
                 ]
                 .as_ref(),
             )
-            .split(f.size());
+            .split(area)
+    }
 
-        f.render_widget(paragraph, chunks[0]);
-        f.render_widget(Self::help(), chunks[1]);
+    /// The resolved command and the script, cut to its first line unless expanded
+    fn command_lines<'a>(&self, script: &Script) -> Vec<Spans<'a>> {
+        let dim = Style::default().fg(Color::DarkGray);
+        let mut lines = vec![Spans::from(vec![Span::styled(
+            format!("$ {}", script.resolved_command()),
+            dim,
+        )])];
+        if let Some(file) = &script.script_file {
+            lines.push(Spans::from(vec![Span::styled(format!("│ {file}"), dim)]));
+            return lines;
+        }
+        let body: Vec<&str> = script.script.lines().collect();
+        let shown = if self.expand_scripts { body.len() } else { 1 };
+        lines.extend(
+            body.iter()
+                .take(shown)
+                .map(|l| Spans::from(vec![Span::styled(format!("│ {l}"), dim)])),
+        );
+        if body.len() > shown {
+            lines.push(Spans::from(vec![Span::styled(
+                format!("│ … {} more lines, <e> to expand", body.len() - shown),
+                dim,
+            )]));
+        }
+        lines
+    }
+
+    /// Output of a running task so far, under a header per started script when it has several
+    fn live_lines<'a>(&self, job_thread: &JobThread) -> Vec<Spans<'a>> {
+        let scripts = job_thread.task.scripts();
+        let lines = |progress: &Progress| -> Vec<Spans<'a>> {
+            String::from_utf8_lossy(&self.stream.live(progress))
+                .lines()
+                .map(|l| Spans::from(vec![Span::raw(String::from(l))]))
+                .collect()
+        };
+        if let [_] = scripts[..] {
+            return lines(&job_thread.progress[0]);
+        }
+        scripts
+            .iter()
+            .zip(job_thread.progress.iter())
+            .filter(|(_, progress)| {
+                progress.attempts.load(Ordering::SeqCst) + progress.warmups.load(Ordering::SeqCst) > 0
+            })
+            .flat_map(|(script, progress)| {
+                let title = Spans::from(vec![Span::styled(
+                    format!("── {} ── ", script.name),
+                    Style::default().add_modifier(Modifier::BOLD),
+                )]);
+                std::iter::once(title)
+                    .chain(lines(progress))
+                    .chain([Spans::from(vec![Span::raw("")])])
+            })
+            .collect()
+    }
+
+    /// Warning with the count and first match of the script's error markers
+    fn marker_line<'a>(script: &Script, output: &Output) -> Option<Spans<'a>> {
+        let markers = script.markers(output);
+        markers.first().map(|first| {
+            Spans::from(vec![Span::styled(
+                format!("⚠ {} errors in output, first: {first}", markers.len()),
+                Style::default().fg(Color::Yellow),
+            )])
+        })
+    }
+
+    fn in_progress<'a>(&self, job_thread: &JobThread) -> Span<'a> {
+        if job_thread.is_queued() {
+            return Self::status_span(Status::Queued);
+        }
+        let text = match job_thread.warming_up() {
+            Some((run, total)) => format!("{} Warming up ({run}/{total})", SPINNER[self.spinner]),
+            None => format!("{} {}", SPINNER[self.spinner], Status::InProgress),
+        };
+        Span::styled(text, Self::status_style(&Status::InProgress))
+    }
+
+    fn status_style(status: &Status) -> Style {
+        match status {
+            Status::InProgress => Style::default().fg(Color::Yellow),
+            Status::Complete => Style::default().fg(Color::Green),
+            Status::Skipped | Status::Queued => Style::default().fg(Color::DarkGray),
+            _ => Style::default().fg(Color::Red),
+        }
+    }
+
+    fn status_cell<'a>(status: Status) -> Cell<'a> {
+        Cell::from(status.to_string()).style(Self::status_style(&status))
+    }
+
+    fn status_span<'a>(status: Status) -> Span<'a> {
+        Span::styled(status.to_string(), Self::status_style(&status))
     }
 
-    fn help<'a>() -> Paragraph<'a> {
-        let commands = vec![
-            "<ctrl+c>: Quit",
+    /// Counts of complete, failed and in progress tasks
+    fn summary<'a>(runner: &JobRunner) -> Paragraph<'a> {
+        let (complete, failed, running) = runner.counts();
+        let mut spans = vec![
+            Span::styled(
+                format!("{complete} complete"),
+                Self::status_style(&Status::Complete),
+            ),
+            Span::raw(" ⎯ "),
+            Span::styled(format!("{failed} failed"), Self::status_style(&Status::Error)),
+            Span::raw(" ⎯ "),
+            Span::styled(
+                format!("{running} in progress"),
+                Self::status_style(&Status::InProgress),
+            ),
+        ];
+        if running == 0 {
+            spans.push(Span::raw(format!(" ⎯ {}", runner.summary())));
+        }
+        if runner.timed_out() {
+            spans.push(Span::styled(
+                " ⎯ JOB TIMED OUT",
+                Style::default().fg(Color::Red).add_modifier(Modifier::BOLD),
+            ));
+        }
+        Paragraph::new(Spans::from(spans)).alignment(Alignment::Center)
+    }
+
+    fn help<'a>(&self, paused: bool) -> Paragraph<'a> {
+        if let Some((message, _)) = &self.message {
+            return Paragraph::new(Spans::from(vec![Span::styled(
+                message.clone(),
+                Style::default().add_modifier(Modifier::BOLD),
+            )]))
+            .alignment(Alignment::Center);
+        }
+        if self.searching {
+            return Paragraph::new(Spans::from(vec![
+                Span::styled("/", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(format!("{}▏ <enter>: Keep filter ⎯⎯⎯  <esc>: Clear", self.search)),
+            ]))
+            .alignment(Alignment::Center);
+        }
+        if paused {
+            return Paragraph::new(Spans::from(vec![Span::styled(
+                "PAUSED — job scheduling halted, <space> to resume",
+                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            )]))
+            .alignment(Alignment::Center);
+        }
+
+        let commands = [
+            "<q/ctrl+c>: Quit",
             "<↑/↓>: Navigate",
             "<enter>: View full logs",
             "<esc> Go back to Job view",
+            "<d>: Filter by destination",
+            "<t>: Filter by tag",
+            "<x>: Cancel task",
+            "<r>: Rerun failed",
+            "</>: Search",
+            "<space>: Pause",
+            "<y>: Copy status/output",
+            "<tab>: stdout/stderr",
+            "<e>: Expand scripts",
+            "<pgup/pgdn>: Scroll",
         ];
 
         let text = vec![Spans::from(vec![Span::raw(commands.join(" ⎯⎯⎯  "))])];
@@ -292,21 +690,40 @@ impl State {
             .wrap(Wrap { trim: true });
         paragraph
     }
+}
 
-    fn title<'a>() -> Paragraph<'a> {
-        let text = vec![Spans::from(vec![Span::raw("♚ Checkmate ♔")])];
+/// Set the terminal's clipboard with an OSC 52 escape, which also works over ssh.
+/// Fails as unsupported when stdout isn't a terminal that could take the escape.
+fn copy(text: &str) -> std::io::Result<()> {
+    use base64::Engine;
+    use std::io::{IsTerminal, Write};
 
-        let paragraph = Paragraph::new(text)
-            .block(
-                Block::default()
-                    .title("")
-                    .borders(Borders::NONE)
-                    .border_type(BorderType::Rounded),
-            )
-            // .style(Style::default().fg(Color::White).bg(Color::Black))
-            .alignment(Alignment::Center)
-            .wrap(Wrap { trim: true });
-        paragraph
+    let mut stdout = std::io::stdout();
+    if !clipboard_available(stdout.is_terminal(), std::env::var("TERM").ok().as_deref()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "No clipboard available",
+        ));
+    }
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    write!(stdout, "\x1b]52;c;{encoded}\x07")?;
+    stdout.flush()
+}
+
+/// Whether the terminal could take an OSC 52 escape. Whether it then sets the clipboard
+/// can't be told, terminals don't answer it.
+fn clipboard_available(terminal: bool, term: Option<&str>) -> bool {
+    terminal && term != Some("dumb")
+}
+
+/// Shorten `s` to `width` characters, marking the cut with an ellipsis
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        s.to_string()
+    } else {
+        let mut short: String = s.chars().take(width.saturating_sub(1)).collect();
+        short.push('…');
+        short
     }
 }
 
@@ -314,3 +731,227 @@ pub enum DrawMode {
     Job,
     Task,
 }
+
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn of<'a>(&self, output: &'a Output) -> &'a [u8] {
+        match self {
+            Stream::Stdout => &output.stdout,
+            Stream::Stderr => &output.stderr,
+        }
+    }
+
+    /// What a running script has written to this stream so far
+    fn live(&self, progress: &Progress) -> Vec<u8> {
+        let buffer = match self {
+            Stream::Stdout => &progress.stdout,
+            Stream::Stderr => &progress.stderr,
+        };
+        buffer.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checkmate::{Job, Remote, Task};
+    use tui::{backend::TestBackend, Terminal};
+
+    /// Run a job of `tasks` to the end
+    fn run(tasks: Vec<Task>) -> JobRunner {
+        let runner = Job::new("draw", tasks).run();
+        while runner.finished() < runner.threads.len() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        runner
+    }
+
+    /// Draw one frame, as rows of text
+    fn render(state: &mut State, runner: &JobRunner, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| state.draw(f, runner)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|cell| cell.symbol.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn destination_key_shows_only_the_selected_destination() {
+        // Refused straight away, these never get as far as running
+        let remote = Destination::Remote(Remote {
+            port: Some(1),
+            connect_timeout: Some(1),
+            ..Remote::from("127.0.0.1")
+        });
+        let on_remote = |name: &str| {
+            Task::Script(Script::builder(name, "true").destination(remote.clone()).build())
+        };
+        let runner = run(vec![
+            Task::local("a", "true"),
+            on_remote("b"),
+            Task::local("c", "true"),
+            on_remote("d"),
+        ]);
+        let mut state = State::default();
+        state.down_key(3);
+
+        state.destination_key(&runner);
+        assert_eq!(state.visible(&runner), vec![1, 3]);
+        assert_eq!(state.selected_thread(&runner), Some(1));
+
+        state.destination_key(&runner);
+        assert_eq!(state.visible(&runner), vec![0, 1, 2, 3]);
+        assert_eq!(state.selected_thread(&runner), Some(1));
+    }
+
+    #[test]
+    fn long_serial_names_are_truncated_to_fit() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly 10", 10), "exactly 10");
+        assert_eq!(truncate("a bit too long", 10), "a bit too…");
+
+        let steps = (1..=8).map(|i| Script::new(format!("step number {i}"), "true")).collect();
+        let runner = run(vec![Task::Serial(steps)]);
+        let name = runner.threads[0].task.name();
+        assert!(name.contains(" => step number 8"));
+
+        let mut state = State::default();
+        let rows = render(&mut state, &runner, 100, 20);
+        let row = rows.iter().find(|row| row.contains("step number 1")).unwrap();
+        assert!(row.contains('…') && !row.contains("step number 2"), "{row}");
+        assert!(rows.iter().all(|row| row.chars().count() == 100));
+
+        state.enter_key();
+        let rows = render(&mut state, &runner, 100, 20);
+        let title = &rows[1];
+        assert!(title.contains("Task[0]: step number 1") && title.contains('…'), "{title}");
+        assert!(title.contains("Complete"), "{title}");
+        // The full name moves into the body when the title can't hold it
+        let body = rows[2..].concat();
+        assert!(body.contains("Task: step number 1 => step number 2"), "{body}");
+    }
+
+    #[test]
+    fn serial_output_is_split_under_a_header_per_step() {
+        let runner = run(vec![Task::Serial(vec![
+            Script::new("write", "echo first"),
+            Script::new("read", "echo second"),
+        ])]);
+        let mut state = State::default();
+        state.enter_key();
+        let rows = render(&mut state, &runner, 80, 20);
+        let lines: Vec<&str> = rows
+            .iter()
+            .map(|row| row.trim_matches(|c| c == ' ' || c == '│'))
+            .collect();
+
+        let header = |name: &str| {
+            let header = format!("── {name} ── Complete");
+            lines.iter().position(|l| l.starts_with(&header)).unwrap()
+        };
+        let (write, read) = (header("step 1: write"), header("step 2: read"));
+        let first = lines.iter().position(|l| *l == "first").unwrap();
+        let second = lines.iter().position(|l| *l == "second").unwrap();
+        assert!(write < first && first < read && read < second, "{lines:#?}");
+    }
+
+    #[test]
+    fn status_column_reports_the_first_unsuccessful_script() {
+        let runner = run(vec![
+            Task::Parallel(vec![
+                Script::new("ok", "true"),
+                Script::new("broken", "exit 2"),
+                Script::new("also ok", "true"),
+            ]),
+            Task::Serial(vec![Script::new("one", "true"), Script::new("two", "true")]),
+        ]);
+        assert_eq!(runner.threads[0].status(), Status::Failed(2));
+        assert_eq!(runner.threads[1].status(), Status::Complete);
+
+        let rows = render(&mut State::default(), &runner, 200, 12);
+        let row = |name: &str| rows.iter().find(|row| row.contains(name)).unwrap().clone();
+        assert!(row("ok || broken").contains("Failed (exit 2)"), "{rows:#?}");
+        assert!(row("one => two").contains("Complete"), "{rows:#?}");
+    }
+
+    #[test]
+    fn task_view_shows_the_command_and_a_preview_of_the_script() {
+        let runner = run(vec![Task::local("deploy", "echo one\necho two\necho three")]);
+        let command = runner.threads[0].task.scripts()[0].resolved_command();
+        assert!(command.starts_with("bash ") && command.ends_with("_deploy.sh"), "{command}");
+
+        let mut state = State::default();
+        state.enter_key();
+        let text = |state: &mut State| render(state, &runner, 120, 20).join("\n");
+        let collapsed = text(&mut state);
+        assert!(collapsed.contains(&format!("$ {command}")), "{collapsed}");
+        assert!(collapsed.contains("│ echo one"), "{collapsed}");
+        assert!(!collapsed.contains("│ echo two"), "{collapsed}");
+        assert!(collapsed.contains("│ … 2 more lines, <e> to expand"), "{collapsed}");
+
+        state.expand_key();
+        let expanded = text(&mut state);
+        assert!(expanded.contains("│ echo two") && expanded.contains("│ echo three"), "{expanded}");
+        assert!(!expanded.contains("more lines"), "{expanded}");
+    }
+
+    #[test]
+    fn clipboard_needs_a_terminal_that_takes_escapes() {
+        assert!(clipboard_available(true, Some("xterm-256color")));
+        assert!(clipboard_available(true, None));
+        assert!(!clipboard_available(true, Some("dumb")));
+        assert!(!clipboard_available(false, Some("xterm-256color")));
+    }
+
+    #[test]
+    fn status_style_colors_each_status() {
+        let color = |status: Status| State::status_style(&status).fg;
+        assert_eq!(color(Status::Queued), Some(Color::DarkGray));
+        assert_eq!(color(Status::InProgress), Some(Color::Yellow));
+        assert_eq!(color(Status::Complete), Some(Color::Green));
+        assert_eq!(color(Status::Skipped), Some(Color::DarkGray));
+        for failed in [
+            Status::Failed(1),
+            Status::Signaled(Some(9)),
+            Status::Signaled(None),
+            Status::Error,
+            Status::TimedOut,
+            Status::DownloadFailed,
+            Status::ConnectionFailed,
+            Status::ShellNotFound("fish".into()),
+            Status::Cancelled,
+            Status::Stderr,
+            Status::Markers(2),
+        ] {
+            assert_eq!(color(failed.clone()), Some(Color::Red), "{failed}");
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_output_is_drawn_lossily() {
+        let bytes = r"printf '\xff\xfe bytes\n'";
+        let runner = run(vec![
+            Task::local("single", bytes),
+            Task::Serial(vec![Script::new("step", bytes)]),
+        ]);
+        assert_eq!(runner.results()[0].outputs()[0].as_ref().unwrap().stdout, b"\xff\xfe bytes\n");
+
+        let mut state = State::default();
+        let rows = render(&mut state, &runner, 100, 20).concat();
+        assert_eq!(rows.matches("\u{fffd}\u{fffd} bytes").count(), 2, "{rows}");
+        for _ in 0..2 {
+            state.enter_key();
+            let rows = render(&mut state, &runner, 100, 20).concat();
+            assert!(rows.contains("\u{fffd}\u{fffd} bytes"), "{rows}");
+            state.back_key();
+            state.down_key(1);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use crate::ws::Broadcaster;
+use checkmate::{junit, Job, JobRunner, Status};
+use clap::ValueEnum;
+use crossterm::style::Stylize;
+use serde_json::json;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::time::Instant;
+use std::{thread, time::Duration};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Each task's status and output as it finishes
+    Text,
+    /// One line of key=value metrics once the job is done
+    Metrics,
+    /// One JSON document with every task once the job is done.
+    /// Output bytes are decoded as UTF-8, invalid sequences become U+FFFD.
+    Json,
+}
+
+/// Run a job without the TUI, printing each task as it finishes.
+/// With `rerun_failed` the tasks that didn't succeed run once more and the job is reported with
+/// their second run in place of the first. Writes a JUnit report to `junit_path` if given and records the run
+/// in the history at `history`. Output and status changes also go to `broadcaster`'s clients.
+/// Returns true when every task succeeded.
+pub fn run(
+    job: Job,
+    format: OutputFormat,
+    junit_path: Option<&Path>,
+    rerun_failed: bool,
+    history: Option<&Path>,
+    broadcaster: Option<Broadcaster>,
+) -> bool {
+    let start = Instant::now();
+    let mut runner = job.run();
+    if let Some(broadcaster) = &broadcaster {
+        broadcaster.watch(&runner);
+    }
+    watch(&runner, format);
+
+    let failed = runner.failed_tasks();
+    if rerun_failed && !failed.is_empty() {
+        if format == OutputFormat::Text {
+            println!("== rerunning {} failed tasks: {}", failed.len(), failed.join(", "));
+        }
+        let rerun = runner.failed_job().run();
+        if let Some(broadcaster) = &broadcaster {
+            broadcaster.watch(&rerun);
+        }
+        watch(&rerun, format);
+        runner.merge_rerun(rerun);
+    }
+    crate::record(history, &runner);
+    let failed = runner.failed_tasks().len();
+    let mut success = failed == 0;
+
+    if let Some(broadcaster) = broadcaster {
+        broadcaster.watch(&runner);
+        broadcaster.close();
+    }
+
+    if let (Some(output), Some(hook)) = (runner.run_on_complete(), &runner.job.on_complete) {
+        if format == OutputFormat::Text {
+            println!("== on_complete [{}]", hook.status(&output));
+            match output {
+                Ok(x) => print!("{}", String::from_utf8_lossy(&x.stdout)),
+                Err(e) => println!("{e}"),
+            }
+        }
+    }
+
+    if format == OutputFormat::Text {
+        println!("== summary: {}", runner.summary());
+    }
+
+    print_table(&runner);
+
+    if let (true, Some(secs)) = (runner.timed_out(), runner.job.timeout) {
+        eprintln!("Job timed out after {secs}s, unfinished tasks were cancelled");
+        success = false;
+    }
+
+    if format == OutputFormat::Metrics {
+        println!("{}", metrics(&runner, start.elapsed()));
+    }
+
+    if format == OutputFormat::Json {
+        println!("{}", report(&runner));
+    }
+
+    if let Some(path) = junit_path {
+        if let Err(e) = std::fs::write(path, junit(&runner)) {
+            eprintln!("Failed to write {}: {e}", path.display());
+            success = false;
+        }
+    }
+
+    success
+}
+
+/// Wait for every task, printing each as it finishes in the text format
+fn watch(runner: &JobRunner, format: OutputFormat) {
+    let mut reported = vec![false; runner.threads.len()];
+
+    while reported.contains(&false) {
+        for (i, jr) in runner.threads.iter().enumerate() {
+            if reported[i] {
+                continue;
+            }
+            if let Ok(result) = &*jr.thread.borrow() {
+                reported[i] = true;
+                if format != OutputFormat::Text {
+                    continue;
+                }
+                let status = jr.task.status(result);
+                let attempts = jr.attempts();
+                if jr.task.scripts().len() > 1 {
+                    println!("== {} [{}]", jr.task.name(), status);
+                } else {
+                    println!("== {} [{}]{}", jr.task.name(), status, retried(attempts[0]));
+                }
+
+                for ((script, output), attempts) in
+                    jr.task.scripts().iter().zip(result.outputs()).zip(attempts)
+                {
+                    if jr.task.scripts().len() > 1 {
+                        println!(
+                            "-- {} [{}]{}",
+                            script.name,
+                            script.status(output),
+                            retried(attempts)
+                        );
+                    }
+                    match output {
+                        Ok(x) => print!("{}", String::from_utf8_lossy(&x.stdout)),
+                        Err(e) => println!("{e}"),
+                    }
+                }
+            }
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Task counts and the job's duration as one line of key=value pairs
+fn metrics(runner: &JobRunner, duration: Duration) -> String {
+    format!(
+        "checkmate_tasks_total={} checkmate_tasks_failed={} checkmate_duration_seconds={:.1}",
+        runner.threads.len(),
+        runner.failed_tasks().len(),
+        duration.as_secs_f64()
+    )
+}
+
+/// Every task's status, duration and the exit code and output of each of its scripts
+fn report(runner: &JobRunner) -> serde_json::Value {
+    let tasks: Vec<_> = runner
+        .threads
+        .iter()
+        .map(|jr| {
+            let thread = jr.thread.borrow();
+            let Ok(result) = &*thread else {
+                return json!({ "name": jr.task.name(), "type": jr.task.type_name() });
+            };
+            let scripts: Vec<_> = jr
+                .task
+                .scripts()
+                .iter()
+                .zip(result.outputs())
+                .map(|(script, output)| match output {
+                    Ok(ok) => json!({
+                        "name": script.name,
+                        "status": script.status(output).to_string(),
+                        "exit_code": ok.status.code(),
+                        "stdout": String::from_utf8_lossy(&ok.stdout),
+                        "stderr": String::from_utf8_lossy(&ok.stderr),
+                    }),
+                    Err(e) => json!({
+                        "name": script.name,
+                        "status": script.status(output).to_string(),
+                        "exit_code": null,
+                        "error": e.to_string(),
+                    }),
+                })
+                .collect();
+            json!({
+                "name": jr.task.name(),
+                "type": jr.task.type_name(),
+                "status": jr.task.status(result).to_string(),
+                "duration_seconds": jr.duration().as_secs_f64(),
+                "scripts": scripts,
+            })
+        })
+        .collect();
+    json!({ "job": runner.job.name, "tasks": tasks })
+}
+
+/// Table of every task's status, duration and exit code on stderr, leaving stdout to the output format
+fn print_table(runner: &JobRunner) {
+    let rows: Vec<_> = runner
+        .threads
+        .iter()
+        .map(|jr| {
+            let status = jr.status();
+            // The first unsuccessful script's code, like the status
+            let code = match &*jr.thread.borrow() {
+                Ok(result) => jr
+                    .task
+                    .scripts()
+                    .iter()
+                    .zip(result.outputs())
+                    .find(|(script, output)| script.status(output) != Status::Complete)
+                    .or_else(|| jr.task.scripts().iter().zip(result.outputs()).next_back())
+                    .and_then(|(_, output)| output.as_ref().ok())
+                    .and_then(|output| output.status.code()),
+                Err(_) => None,
+            };
+            let code = code.map_or("-".to_string(), |c| c.to_string());
+            let duration = format!("{:.2}s", jr.duration().as_secs_f64());
+            (jr.task.name(), status, duration, code)
+        })
+        .collect();
+
+    let width = |title: &str, cells: &mut dyn Iterator<Item = usize>| cells.fold(title.len(), usize::max);
+    let name_width = width("TASK", &mut rows.iter().map(|r| r.0.chars().count()));
+    let status_width = width("STATUS", &mut rows.iter().map(|r| r.1.to_string().len()));
+    let duration_width = width("DURATION", &mut rows.iter().map(|r| r.2.len()));
+
+    let color = std::io::stderr().is_terminal();
+    eprintln!(
+        "{:<name_width$}  {:<status_width$}  {:>duration_width$}  EXIT",
+        "TASK", "STATUS", "DURATION"
+    );
+    for (name, status, duration, code) in &rows {
+        let padded = format!("{:<status_width$}", status.to_string());
+        let padded = match (color, status) {
+            (false, _) => padded,
+            (true, Status::Complete) => padded.green().to_string(),
+            (true, Status::Skipped | Status::Queued) => padded.dark_grey().to_string(),
+            (true, Status::InProgress) => padded.yellow().to_string(),
+            (true, _) => padded.red().to_string(),
+        };
+        eprintln!("{name:<name_width$}  {padded}  {duration:>duration_width$}  {code}");
+    }
+}
+
+/// Note scripts that needed more than one attempt
+fn retried(attempts: u32) -> String {
+    if attempts > 1 {
+        format!(" after {attempts} attempts")
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checkmate::Task;
+
+    /// Run a job of local `scripts`, given as name and body, to the end
+    fn run(scripts: &[(&str, &str)]) -> JobRunner {
+        let tasks = scripts.iter().map(|(name, script)| Task::local(*name, *script)).collect();
+        let runner = Job::new("headless", tasks).run();
+        runner.wait_all().ok();
+        runner
+    }
+
+    #[test]
+    fn metrics_count_tasks_and_failures() {
+        let runner = run(&[("a", "true"), ("b", "exit 3"), ("c", "true")]);
+        assert_eq!(
+            metrics(&runner, Duration::from_millis(43_210)),
+            "checkmate_tasks_total=3 checkmate_tasks_failed=1 checkmate_duration_seconds=43.2"
+        );
+    }
+
+    #[test]
+    fn json_report_has_each_task_status_and_exit_codes() {
+        let runner = run(&[("ok", "echo hi"), ("bad", "echo oops >&2; exit 3")]);
+        let parsed: serde_json::Value = serde_json::from_str(&report(&runner).to_string()).unwrap();
+
+        assert_eq!(parsed["job"], "headless");
+        let tasks = parsed["tasks"].as_array().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0]["name"], "ok");
+        assert_eq!(tasks[0]["status"], "Complete");
+        assert_eq!(tasks[0]["scripts"][0]["exit_code"], 0);
+        assert_eq!(tasks[0]["scripts"][0]["stdout"], "hi\n");
+        assert_eq!(tasks[1]["name"], "bad");
+        assert_eq!(tasks[1]["status"], "Failed (exit 3)");
+        assert_eq!(tasks[1]["scripts"][0]["status"], "Failed (exit 3)");
+        assert_eq!(tasks[1]["scripts"][0]["exit_code"], 3);
+        assert_eq!(tasks[1]["scripts"][0]["stderr"], "oops\n");
+    }
+}
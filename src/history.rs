@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Result};
+use checkmate::{JobRunner, Status};
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A finished run, one JSON line of the history file
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Run {
+    pub job: String,
+    /// When the run was recorded, RFC 3339 in UTC
+    pub timestamp: String,
+    pub tasks: Vec<Outcome>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Outcome {
+    pub name: String,
+    pub status: String,
+}
+
+impl Run {
+    pub fn new(runner: &JobRunner) -> Self {
+        Self {
+            job: runner.job.name.clone(),
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            tasks: runner
+                .threads
+                .iter()
+                .map(|jr| Outcome {
+                    name: jr.task.name(),
+                    status: jr.status().to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Timestamp, job and how many tasks succeeded, naming the ones that didn't
+    pub fn line(&self) -> String {
+        let complete_status = Status::Complete.to_string();
+        let complete = self.tasks.iter().filter(|t| t.status == complete_status).count();
+        let mut line = format!(
+            "{}  {}  {complete}/{} complete",
+            self.timestamp,
+            self.job,
+            self.tasks.len()
+        );
+        let failed: Vec<String> = self
+            .tasks
+            .iter()
+            .filter(|t| t.status != complete_status)
+            .map(|t| format!("{} [{}]", t.name, t.status))
+            .collect();
+        if !failed.is_empty() {
+            line.push_str(&format!(", failed: {}", failed.join(", ")));
+        }
+        line
+    }
+}
+
+/// `history.jsonl` in the platform's data directory, e.g. ~/.local/share/checkmate
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "checkmate").map(|dirs| dirs.data_dir().join("history.jsonl"))
+}
+
+/// Add the runner's outcome to the end of the history file, creating it if needed
+pub fn append(path: &Path, runner: &JobRunner) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open history {}: {e}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(&Run::new(runner))?)?;
+    Ok(())
+}
+
+/// The last `count` runs, oldest first. No file is an empty history.
+pub fn last(path: &Path, count: usize) -> Result<Vec<Run>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(anyhow!("Failed to read history {}: {e}", path.display())),
+    };
+    let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+    lines[lines.len().saturating_sub(count)..]
+        .iter()
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| anyhow!("{}: {e}", path.display()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checkmate::{Job, Task};
+
+    fn run(name: &str, script: &str) -> JobRunner {
+        let runner = Job::new(name, vec![Task::local("check", script)]).run();
+        runner.wait_all().ok();
+        runner
+    }
+
+    #[test]
+    fn appended_runs_read_back_oldest_first() {
+        let dir = std::env::temp_dir().join(format!("checkmate-history-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("history.jsonl");
+        assert!(last(&path, 10).unwrap().is_empty());
+
+        append(&path, &run("nightly", "true")).unwrap();
+        append(&path, &run("deploy", "exit 2")).unwrap();
+        let runs = last(&path, 10).unwrap();
+        let latest = last(&path, 1).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let jobs: Vec<&str> = runs.iter().map(|r| r.job.as_str()).collect();
+        assert_eq!(jobs, ["nightly", "deploy"]);
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].job, "deploy");
+
+        let line = |run: &Run| run.line().split_once("  ").unwrap().1.to_string();
+        assert_eq!(line(&runs[0]), "nightly  1/1 complete");
+        assert_eq!(line(&runs[1]), "deploy  0/1 complete, failed: check [Failed (exit 2)]");
+    }
+}
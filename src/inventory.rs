@@ -0,0 +1,235 @@
+use crate::{Destination, Job, Remote, Script, Task};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Named groups of hosts, read from an INI style file:
+///
+/// ```text
+/// [web]
+/// deploy@web1
+/// web2
+/// ```
+///
+/// or, from a `.yaml`/`.yml` file, a map of groups to hosts in either form a destination takes:
+///
+/// ```text
+/// web:
+///   - deploy@web1
+///   - host: web2
+///     port: 2222
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    pub groups: BTreeMap<String, Vec<Remote>>,
+}
+
+impl Inventory {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to read inventory {}: {e}", path.display()))?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+        match extension {
+            "yaml" | "yml" => Self::parse_yaml(&contents),
+            _ => Self::parse(&contents),
+        }
+        .map_err(|e| anyhow!("{}: {e}", path.display()))
+    }
+
+    pub fn parse_yaml(contents: &str) -> Result<Self> {
+        let groups = serde_yaml::from_str::<Option<BTreeMap<String, Vec<Remote>>>>(contents)?;
+        Ok(Self {
+            groups: groups.unwrap_or_default(),
+        })
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut groups: BTreeMap<String, Vec<Remote>> = BTreeMap::new();
+        let mut current = None;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(group) = line.strip_prefix('[') {
+                let group = group
+                    .strip_suffix(']')
+                    .ok_or_else(|| anyhow!("line {}: unterminated group header", i + 1))?
+                    .trim();
+                if group.is_empty() {
+                    return Err(anyhow!("line {}: empty group name", i + 1));
+                }
+                groups.entry(group.to_string()).or_default();
+                current = Some(group.to_string());
+            } else {
+                let group = current.as_ref().ok_or_else(|| {
+                    anyhow!("line {}: host {line} is outside of a [group]", i + 1)
+                })?;
+                groups
+                    .get_mut(group)
+                    .expect("group was inserted with its header")
+                    .push(line.into());
+            }
+        }
+
+        Ok(Self { groups })
+    }
+
+    fn hosts(&self, group: &str) -> Result<&[Remote]> {
+        self.groups
+            .get(group)
+            .map(|hosts| hosts.as_slice())
+            .ok_or_else(|| anyhow!("Unknown inventory group {group}"))
+    }
+
+    /// One copy of the script per host when it targets a group
+    fn expand(&self, script: &Script) -> Result<Vec<Script>> {
+        match &script.destination {
+            Destination::Group(group) => Ok(self
+                .hosts(group)?
+                .iter()
+                .map(|host| Script {
+                    name: format!("{} ({})", script.name, host),
+                    destination: Destination::Remote(host.clone()),
+                    ..script.clone()
+                })
+                .collect()),
+            _ => Ok(vec![script.clone()]),
+        }
+    }
+
+    /// Replace group destinations with the group's hosts. A lone script becomes
+    /// a parallel task, scripts inside serial and parallel tasks are expanded in place.
+    /// Dependencies on an expanded script wait for every host's copy.
+    pub fn resolve(&self, job: &mut Job) -> Result<()> {
+        let mut copies: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for script in job.tasks.iter().flat_map(|t| t.scripts()) {
+            if matches!(script.destination, Destination::Group(_)) {
+                let names = self.expand(script)?.into_iter().map(|s| s.name);
+                copies.entry(script.name.clone()).or_default().extend(names);
+            }
+        }
+
+        for task in job.tasks.iter_mut() {
+            *task = match task {
+                Task::Script(s) if matches!(s.destination, Destination::Group(_)) => {
+                    Task::Parallel(self.expand(s)?)
+                }
+                Task::Script(s) => Task::Script(s.clone()),
+                Task::Serial(ss) => Task::Serial(self.expand_all(ss)?),
+                Task::Parallel(ss) => Task::Parallel(self.expand_all(ss)?),
+            };
+            for script in task.scripts_mut() {
+                script.depends_on = script
+                    .depends_on
+                    .iter()
+                    .flat_map(|name| copies.get(name).cloned().unwrap_or_else(|| vec![name.clone()]))
+                    .collect();
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_all(&self, scripts: &[Script]) -> Result<Vec<Script>> {
+        let mut expanded = vec![];
+        for script in scripts {
+            expanded.extend(self.expand(script)?);
+        }
+        Ok(expanded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_reads_groups_and_hosts() {
+        let inventory = Inventory::parse(
+            "# comment\n[web]\ndeploy@web1\n  web2  \n\n; also a comment\n[ db ]\ndb1\n[empty]\n",
+        )
+        .unwrap();
+
+        let hosts = |group: &str| -> Vec<(Option<String>, String)> {
+            inventory.groups[group]
+                .iter()
+                .map(|r| (r.user.clone(), r.host.clone()))
+                .collect()
+        };
+        assert_eq!(
+            hosts("web"),
+            vec![(Some("deploy".into()), "web1".into()), (None, "web2".into())]
+        );
+        assert_eq!(hosts("db"), vec![(None, "db1".into())]);
+        assert!(hosts("empty").is_empty());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_lines() {
+        let error = |contents: &str| Inventory::parse(contents).unwrap_err().to_string();
+        assert_eq!(error("web1\n"), "line 1: host web1 is outside of a [group]");
+        assert_eq!(error("[web]\nweb1\n[db\n"), "line 3: unterminated group header");
+        assert_eq!(error("[ ]\n"), "line 1: empty group name");
+    }
+
+    #[test]
+    fn yaml_matches_ini() {
+        let yaml = Inventory::parse_yaml(
+            "web:\n  - deploy@web1\n  - host: web2\n    port: 2222\ndb: [db1]\nempty: []\n",
+        )
+        .unwrap();
+        let ini = Inventory::parse("[web]\ndeploy@web1\nweb2\n[db]\ndb1\n[empty]\n").unwrap();
+        assert_eq!(yaml.groups.keys().collect::<Vec<_>>(), ini.groups.keys().collect::<Vec<_>>());
+        assert_eq!(yaml.groups["web"][0], ini.groups["web"][0]);
+        assert_eq!(yaml.groups["web"][1].port, Some(2222));
+        assert_eq!(yaml.groups["db"], ini.groups["db"]);
+
+        assert!(Inventory::parse_yaml("").unwrap().groups.is_empty());
+        assert!(Inventory::parse_yaml("web: deploy@web1\n").is_err());
+    }
+
+    #[test]
+    fn resolve_expands_groups_and_their_dependents() {
+        let inventory = Inventory::parse("[web]\nweb1\nweb2\n").unwrap();
+        let script = |name: &str, destination: serde_json::Value, depends_on: &[&str]| {
+            json!({"name": name, "destination": destination, "environment": "Current",
+                "shell": "Bash", "script": "true", "depends_on": depends_on})
+        };
+        let mut job: Job = serde_json::from_value(json!({"name": "deploy", "tasks": [
+            {"Script": script("deploy", json!({"Group": "web"}), &[])},
+            {"Serial": [
+                script("build", "Local".into(), &[]),
+                script("smoke", json!({"Group": "web"}), &["deploy"]),
+            ]},
+            {"Script": script("notify", "Local".into(), &["deploy", "build"])},
+        ]}))
+        .unwrap();
+
+        inventory.resolve(&mut job).unwrap();
+
+        let Task::Parallel(deploys) = &job.tasks[0] else {
+            panic!("{:?}", job.tasks[0]);
+        };
+        let names: Vec<&str> = deploys.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["deploy (web1)", "deploy (web2)"]);
+        assert_eq!(deploys[1].destination, Destination::Remote("web2".into()));
+
+        let Task::Serial(steps) = &job.tasks[1] else {
+            panic!("{:?}", job.tasks[1]);
+        };
+        let names: Vec<&str> = steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["build", "smoke (web1)", "smoke (web2)"]);
+        assert_eq!(steps[2].depends_on, ["deploy (web1)", "deploy (web2)"]);
+
+        assert_eq!(job.tasks[2].scripts()[0].depends_on, ["deploy (web1)", "deploy (web2)", "build"]);
+
+        let mut unknown: Job = serde_json::from_value(json!({"name": "x", "tasks": [
+            {"Script": script("x", json!({"Group": "db"}), &[])},
+        ]}))
+        .unwrap();
+        assert_eq!(inventory.resolve(&mut unknown).unwrap_err().to_string(), "Unknown inventory group db");
+    }
+}
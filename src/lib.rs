@@ -1,26 +1,92 @@
 use anyhow::{anyhow, Result};
-use openssh::{KnownHosts, Session};
+use openssh::{KnownHosts, Session, SessionBuilder};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_dhall::StaticType;
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
 use std::io::Write;
-use std::path::PathBuf;
-use std::process::{Command, Output, Stdio};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::sync::watch::{channel, Receiver};
 use tokio::runtime::Runtime;
 
-/// Tasks are always ran in parallel
+mod inventory;
+pub use inventory::Inventory;
+mod sessions;
+pub use sessions::SessionPool;
+mod report;
+pub use report::junit;
+
+/// Tasks are ran in parallel, each once the tasks it depends on have succeeded
 #[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
 pub struct Job {
     pub name: String,
     pub tasks: Vec<Task>,
+    /// Directory to write each script's stdout and stderr to
+    pub log_dir: Option<String>,
+    /// Tasks allowed to run at once, the rest wait in a queue
+    pub max_parallel: Option<usize>,
+    /// Seconds the whole job may take before its unfinished tasks are cancelled
+    pub timeout: Option<u64>,
+    /// Ran once every task has finished, with `CHECKMATE_JOB`, `CHECKMATE_TASK_COUNT`,
+    /// `CHECKMATE_COMPLETE_COUNT` and `CHECKMATE_FAILED_COUNT` set in its environment
+    pub on_complete: Option<Script>,
+    /// Settings shared by every remote on a host, unless the remote sets its own
+    #[serde(default)]
+    pub destinations: Vec<DestinationDefaults>,
+    /// Ask before running even when every script is local, jobs with remotes always ask
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// Defaults for remotes on `host`
+#[derive(Clone, Debug, Default, Serialize, Deserialize, StaticType, JsonSchema)]
+pub struct DestinationDefaults {
+    pub host: String,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    /// Path to the private key to authenticate with
+    pub identity_file: Option<String>,
+    pub compression: Option<bool>,
+    pub cipher: Option<String>,
+    /// Seconds to wait for the ssh connection
+    pub connect_timeout: Option<u64>,
+    /// Directory scripts are uploaded to and ran from
+    pub tmp_dir: Option<String>,
+}
+
+impl DestinationDefaults {
+    /// Fill in the settings `remote` leaves unset
+    fn apply(&self, remote: &mut Remote) {
+        remote.port = remote.port.or(self.port);
+        remote.user = remote.user.take().or_else(|| self.user.clone());
+        remote.identity_file = remote.identity_file.take().or_else(|| self.identity_file.clone());
+        remote.compression = remote.compression.or(self.compression);
+        remote.cipher = remote.cipher.take().or_else(|| self.cipher.clone());
+        remote.connect_timeout = remote.connect_timeout.or(self.connect_timeout);
+        remote.tmp_dir = remote.tmp_dir.take().or_else(|| self.tmp_dir.clone());
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct JobThread {
     pub task: Task,
     pub thread: Receiver<Result<TaskResult>>,
+    /// Progress of each script, updated while running
+    pub progress: Arc<Vec<Progress>>,
+    /// When the task left the queue, unset while it waits for its dependencies or a free slot
+    pub started: Arc<Mutex<Option<Instant>>>,
+    /// How long the task took, set once it finishes
+    pub elapsed: Arc<Mutex<Option<Duration>>>,
+    /// Stops the task, see [`CancelToken::cancel`]
+    pub cancel: CancelToken,
 }
 
 
@@ -28,197 +94,3786 @@ pub struct JobThread {
 pub struct JobRunner {
     pub job: Job,
     pub threads: Vec<JobThread>,
+    pub control: Control,
+    /// Set once the job's timeout cancelled its unfinished tasks
+    timed_out: Arc<AtomicBool>,
+}
+
+/// Shared between the UI and the task threads to steer a running job
+#[derive(Clone, Debug, Default)]
+pub struct Control {
+    paused: Arc<AtomicBool>,
+    /// Mutex keys held by running scripts
+    locks: Arc<(Mutex<HashSet<String>>, Condvar)>,
+    /// Tasks allowed to run at once, unlimited when unset
+    max_parallel: Option<usize>,
+    /// Number of tasks running
+    running: Arc<(Mutex<usize>, Condvar)>,
+    /// ssh sessions shared by the scripts on each remote
+    pub sessions: SessionPool,
+    /// Set for each task, stops the scripts ran through this control
+    pub cancel: CancelToken,
+}
+
+/// Kills a task's running script and stops the rest from starting
+#[derive(Clone, Debug)]
+pub struct CancelToken(Arc<tokio::sync::watch::Sender<bool>>);
+
+impl Default for CancelToken {
+    fn default() -> Self {
+        Self(Arc::new(tokio::sync::watch::channel(false).0))
+    }
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once cancelled
+    async fn cancelled(&self) {
+        let mut cancelled = self.0.subscribe();
+        while !*cancelled.borrow_and_update() {
+            // The sender lives as long as self, so this can't fail
+            let _ = cancelled.changed().await;
+        }
+    }
+}
+
+/// Totals over a job's finished tasks
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// stdout and stderr captured
+    pub bytes: usize,
+    /// From the first task starting to the last one finishing
+    pub wall: Duration,
+    /// Sum of every task's duration
+    pub task_time: Duration,
+}
+
+impl Summary {
+    /// How many tasks were running at once on average
+    pub fn parallelism(&self) -> f64 {
+        if self.wall.is_zero() {
+            0.0
+        } else {
+            self.task_time.as_secs_f64() / self.wall.as_secs_f64()
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes captured, {:.1}s wall, {:.1}s task time, {:.1}x parallel",
+            self.bytes,
+            self.wall.as_secs_f64(),
+            self.task_time.as_secs_f64(),
+            self.parallelism()
+        )
+    }
+}
+
+/// Runs of a script started so far
+#[derive(Debug, Default)]
+pub struct Progress {
+    /// Discarded warmup runs
+    pub warmups: AtomicU32,
+    /// Measured runs, more than one when retried
+    pub attempts: AtomicU32,
+    /// Output of the current run so far, cut to `max_output_bytes` like the finished output
+    pub stdout: Mutex<Vec<u8>>,
+    pub stderr: Mutex<Vec<u8>>,
+    /// Where each run's whole output is written as it arrives, see [`Job::log_dir`]
+    pub log_dir: Option<PathBuf>,
+    /// Variables set for each run on top of the script's environment
+    pub env: Vec<(String, String)>,
+}
+
+impl Progress {
+    fn clear(&self) {
+        for buffer in [&self.stdout, &self.stderr] {
+            buffer.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    /// Create `<name>.stdout.log` and `<name>.stderr.log` for a run that just started,
+    /// replacing the previous attempt's. A log that can't be created is only reported.
+    fn log_files(&self, script: &str) -> [Option<File>; 2] {
+        let Some(dir) = &self.log_dir else {
+            return [None, None];
+        };
+        let name = script.replace('/', "_");
+        ["stdout", "stderr"].map(|stream| {
+            let path = dir.join(format!("{name}.{stream}.log"));
+            std::fs::create_dir_all(dir)
+                .and_then(|_| File::create(&path))
+                .map_err(|e| tracing::error!("Failed to create log {}: {e}", path.display()))
+                .ok()
+        })
+    }
+}
+
+/// Frees a task slot when dropped
+pub struct SlotGuard<'a> {
+    control: &'a Control,
+}
+
+impl Drop for SlotGuard<'_> {
+    fn drop(&mut self) {
+        let (running, finished) = &*self.control.running;
+        *running.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        finished.notify_one();
+    }
+}
+
+/// Releases a mutex key when dropped
+pub struct LockGuard<'a> {
+    control: &'a Control,
+    key: String,
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        let (held, released) = &*self.control.locks;
+        held.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+        released.notify_all();
+    }
+}
+
+impl Control {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Pause or resume, running scripts carry on but no new ones start while paused
+    pub fn toggle_pause(&self) {
+        self.paused.fetch_xor(true, Ordering::SeqCst);
+    }
+
+    /// Block until the job is resumed, or the task is cancelled
+    pub fn wait_while_paused(&self) -> Result<()> {
+        while self.is_paused() {
+            if self.cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
+    /// Block until no other script holds `key`, then hold it until the guard is dropped.
+    /// Gives up if the task is cancelled while waiting.
+    pub fn lock(&self, key: &str) -> Result<LockGuard<'_>> {
+        let (held, released) = &*self.locks;
+        let mut held = held.lock().unwrap_or_else(|e| e.into_inner());
+        while held.contains(key) {
+            if self.cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            held = released
+                .wait_timeout(held, Duration::from_millis(100))
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        held.insert(key.to_string());
+        Ok(LockGuard {
+            control: self,
+            key: key.to_string(),
+        })
+    }
+
+    /// Block until fewer than `max_parallel` tasks are running, then count as one until the guard is dropped.
+    /// Gives up if the task is cancelled while waiting.
+    pub fn slot(&self) -> Result<SlotGuard<'_>> {
+        let (running, finished) = &*self.running;
+        let mut running = running.lock().unwrap_or_else(|e| e.into_inner());
+        while self.max_parallel.is_some_and(|max| *running >= max) {
+            if self.cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            running = finished
+                .wait_timeout(running, Duration::from_millis(100))
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        *running += 1;
+        Ok(SlotGuard { control: self })
+    }
+
+    /// Run a script once it may start: the job isn't paused and its mutex key is free
+    fn run(&self, script: &Script, progress: &Progress) -> Result<Output> {
+        self.wait_while_paused()?;
+        let _guard = script.mutex.as_deref().map(|key| self.lock(key)).transpose()?;
+        script.run_counted(progress, self)
+    }
+}
+
+impl JobThread {
+    /// A thread for `task` that nothing runs. Its result is whatever is sent to `thread`,
+    /// and `started` and `progress` are left for the caller to fill in.
+    pub fn from_parts(task: Task, thread: Receiver<Result<TaskResult>>) -> Self {
+        let progress = Arc::new(task.scripts().iter().map(|_| Progress::default()).collect());
+        JobThread {
+            task,
+            thread,
+            progress,
+            started: Arc::default(),
+            elapsed: Arc::default(),
+            cancel: CancelToken::default(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.borrow().is_ok()
+    }
+
+    /// Waiting for its dependencies or a free slot
+    pub fn is_queued(&self) -> bool {
+        self.started().is_none() && !self.is_finished()
+    }
+
+    pub fn started(&self) -> Option<Instant> {
+        *self.started.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Time taken so far, or in total once finished. Time spent queued isn't counted.
+    pub fn duration(&self) -> Duration {
+        self.elapsed
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .or_else(|| self.started().map(|s| s.elapsed()))
+            .unwrap_or_default()
+    }
+
+    /// Status of the finished task, or whether it's queued or running
+    pub fn status(&self) -> Status {
+        match &*self.thread.borrow() {
+            Ok(result) => self.task.status(result),
+            Err(_) if self.is_queued() => Status::Queued,
+            Err(_) => Status::InProgress,
+        }
+    }
+
+    /// Compact verdict for sharing, e.g. `deploy: FAILED (exit 2, 12.4s)`
+    pub fn status_line(&self) -> String {
+        let (verdict, detail) = match self.status() {
+            Status::Queued => ("QUEUED", None),
+            Status::InProgress => ("RUNNING", None),
+            Status::Complete => ("OK", None),
+            Status::Failed(code) => ("FAILED", Some(format!("exit {code}"))),
+            Status::Signaled(Some(signal)) => ("FAILED", Some(format!("signal {signal}"))),
+            Status::Signaled(None) => ("FAILED", Some("signal".into())),
+            Status::Error => ("ERROR", None),
+            Status::Skipped => ("SKIPPED", None),
+            Status::TimedOut => ("TIMED OUT", None),
+            Status::DownloadFailed => ("FAILED", Some("download".into())),
+            Status::ConnectionFailed => ("FAILED", Some("connection".into())),
+            Status::ShellNotFound(shell) => ("FAILED", Some(format!("{shell} not found"))),
+            Status::Cancelled => ("CANCELLED", None),
+            Status::Stderr => ("FAILED", Some("stderr".into())),
+            Status::Markers(count) => ("FAILED", Some(format!("{count} errors in output"))),
+        };
+        let duration = format!("{:.1}s", self.duration().as_secs_f64());
+        match detail {
+            Some(detail) => format!("{}: {verdict} ({detail}, {duration})", self.task.name()),
+            None => format!("{}: {verdict} ({duration})", self.task.name()),
+        }
+    }
+
+    /// Attempts made at each script, in declaration order
+    pub fn attempts(&self) -> Vec<u32> {
+        self.progress
+            .iter()
+            .map(|p| p.attempts.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    /// `(run, total)` of the warmup under way, if any
+    pub fn warming_up(&self) -> Option<(u32, u32)> {
+        self.task
+            .scripts()
+            .iter()
+            .zip(self.progress.iter())
+            .find_map(|(script, progress)| {
+                let warmups = progress.warmups.load(Ordering::SeqCst);
+                let started = progress.attempts.load(Ordering::SeqCst) > 0;
+                (warmups > 0 && !started).then_some((warmups, script.warmup))
+            })
+    }
+}
+
+impl JobRunner {
+    /// Number of tasks that have finished running
+    pub fn finished(&self) -> usize {
+        self.threads.iter().filter(|t| t.is_finished()).count()
+    }
+
+    /// Output and timing totals over the finished tasks
+    pub fn summary(&self) -> Summary {
+        let finished = self.threads.iter().filter(|t| t.is_finished());
+        let mut summary = Summary::default();
+        let mut first_start: Option<Instant> = None;
+        let mut last_end: Option<Instant> = None;
+        for t in finished {
+            if let Ok(result) = &*t.thread.borrow() {
+                summary.bytes += result
+                    .outputs()
+                    .iter()
+                    .flatten()
+                    .map(|o| o.stdout.len() + o.stderr.len())
+                    .sum::<usize>();
+            }
+            let duration = t.duration();
+            summary.task_time += duration;
+            if let Some(started) = t.started() {
+                first_start = Some(first_start.map_or(started, |s| s.min(started)));
+                last_end = Some(last_end.map_or(started + duration, |e| e.max(started + duration)));
+            }
+        }
+        if let (Some(start), Some(end)) = (first_start, last_end) {
+            summary.wall = end - start;
+        }
+        summary
+    }
+
+    /// Tasks that are (complete, failed, in progress)
+    pub fn counts(&self) -> (usize, usize, usize) {
+        self.threads
+            .iter()
+            .fold((0, 0, 0), |(complete, failed, running), t| {
+                match &*t.thread.borrow() {
+                    Ok(result) if t.task.status(result) == Status::Complete => {
+                        (complete + 1, failed, running)
+                    }
+                    Ok(_) => (complete, failed + 1, running),
+                    Err(_) => (complete, failed, running + 1),
+                }
+            })
+    }
+
+    /// Block until every task has finished, failing if any of them didn't succeed
+    pub fn wait_all(&self) -> Result<()> {
+        while self.finished() < self.threads.len() {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        self.check()
+    }
+
+    /// Like [`JobRunner::wait_all`], giving up once `timeout` has passed
+    pub fn wait_all_timeout(&self, timeout: Duration) -> Result<()> {
+        let start = std::time::Instant::now();
+        while self.finished() < self.threads.len() {
+            if start.elapsed() >= timeout {
+                return Err(anyhow!(
+                    "{} of {} tasks still running after {timeout:?}",
+                    self.threads.len() - self.finished(),
+                    self.threads.len()
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        self.check()
+    }
+
+    /// Cancel every task that hasn't finished, finished ones keep their results
+    pub fn cancel_all(&self) {
+        for t in self.threads.iter().filter(|t| !t.is_finished()) {
+            t.cancel.cancel();
+        }
+    }
+
+    /// The job ran past its timeout
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::SeqCst)
+    }
+
+    /// Names of the tasks that haven't succeeded, including those still queued or running
+    pub fn failed_tasks(&self) -> Vec<String> {
+        self.threads
+            .iter()
+            .filter(|t| t.status() != Status::Complete)
+            .map(|t| t.task.name())
+            .collect()
+    }
+
+    /// The job again with only the tasks that haven't succeeded, in their original order.
+    /// Dependencies on tasks left out have already been met and are dropped.
+    pub fn failed_job(&self) -> Job {
+        let mut job = self.job.clone();
+        let mut threads = self.threads.iter();
+        job.retain_tasks(|_| threads.next().is_some_and(|t| t.status() != Status::Complete));
+        job
+    }
+
+    /// Put the tasks of `rerun`, a run of [`JobRunner::failed_job`], in place of the ones
+    /// that failed here, so the runner describes the whole job after its second try
+    pub fn merge_rerun(&mut self, rerun: JobRunner) {
+        if rerun.timed_out() {
+            self.timed_out.store(true, Ordering::SeqCst);
+        }
+        let mut rerun_threads = rerun.threads.into_iter();
+        for thread in &mut self.threads {
+            if thread.status() != Status::Complete {
+                if let Some(again) = rerun_threads.next() {
+                    *thread = again;
+                }
+            }
+        }
+        self.control = rerun.control;
+    }
+
+    /// Error naming every finished task that didn't succeed
+    fn check(&self) -> Result<()> {
+        let failed: Vec<String> = self
+            .threads
+            .iter()
+            .filter_map(|t| match &*t.thread.borrow() {
+                Ok(result) => match t.task.status(result) {
+                    Status::Complete => None,
+                    status => Some(format!("{} [{status}]", t.task.name())),
+                },
+                Err(_) => None,
+            })
+            .collect();
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Tasks failed: {}", failed.join(", ")))
+        }
+    }
+
+    /// Run the job's `on_complete` script, if it has one, describing the outcome in its environment.
+    /// Remote hooks share the job's sessions.
+    pub fn run_on_complete(&self) -> Option<Result<Output>> {
+        let hook = self.job.on_complete.as_ref()?;
+        let (complete, failed, _) = self.counts();
+        let progress = Progress {
+            env: vec![
+                ("CHECKMATE_JOB".into(), self.job.name.clone()),
+                ("CHECKMATE_TASK_COUNT".into(), self.threads.len().to_string()),
+                ("CHECKMATE_COMPLETE_COUNT".into(), complete.to_string()),
+                ("CHECKMATE_FAILED_COUNT".into(), failed.to_string()),
+            ],
+            ..Progress::default()
+        };
+        // Not any task's, so it can't be cancelled
+        let control = Control {
+            cancel: CancelToken::default(),
+            ..self.control.clone()
+        };
+        Some(control.run(hook, &progress))
+    }
+
+    /// Results of the finished tasks in job order, tasks still running are left out
+    pub fn results(&self) -> Vec<TaskResult> {
+        self.threads
+            .iter()
+            .filter_map(|t| t.thread.borrow().as_ref().ok().cloned())
+            .collect()
+    }
+}
+
+/// Run a job to completion without a UI, giving each task's result in job order.
+/// Fails if the job is invalid, tasks that fail are reported in their results.
+pub fn run_job(job: Job) -> Result<Vec<TaskResult>> {
+    job.validate()?;
+    let runner = job.run();
+    let _ = runner.wait_all();
+    Ok(runner.results())
 }
 
 impl Job {
-    pub fn run(self) -> JobRunner {
-        JobRunner {
+    /// A job of `tasks` with every setting left at its default
+    pub fn new(name: impl Into<String>, tasks: Vec<Task>) -> Self {
+        Job {
+            name: name.into(),
+            tasks,
+            log_dir: None,
+            max_parallel: None,
+            timeout: None,
+            on_complete: None,
+            destinations: vec![],
+            confirm: false,
+        }
+    }
+
+    /// Merge `destinations` into every remote on a matching host
+    pub fn apply_destination_defaults(&mut self) {
+        for task in self.tasks.iter_mut() {
+            for script in task.scripts_mut() {
+                if let Destination::Remote(remote) = &mut script.destination {
+                    if let Some(defaults) = self.destinations.iter().find(|d| d.host == remote.host) {
+                        defaults.apply(remote);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Every remote the job's scripts and hook run on, without repeats
+    pub fn remotes(&self) -> Vec<&Remote> {
+        let mut remotes: Vec<&Remote> = vec![];
+        let scripts = self.tasks.iter().flat_map(|t| t.scripts()).chain(&self.on_complete);
+        for script in scripts {
+            if let Destination::Remote(remote) = &script.destination {
+                if !remotes.contains(&remote) {
+                    remotes.push(remote);
+                }
+            }
+        }
+        remotes
+    }
+
+    /// Keep only the tasks `keep` returns true for, in order. Dependencies on the
+    /// tasks left out are dropped, the rest run without waiting for them.
+    /// Other dependencies are kept as they are, unknown names included.
+    pub fn retain_tasks(&mut self, mut keep: impl FnMut(&Task) -> bool) {
+        let mut removed = HashSet::new();
+        self.tasks.retain(|task| {
+            let kept = keep(task);
+            if !kept {
+                removed.extend(task.scripts().iter().map(|s| s.name.clone()));
+            }
+            kept
+        });
+        for task in &self.tasks {
+            for script in task.scripts() {
+                removed.remove(&script.name);
+            }
+        }
+        for task in self.tasks.iter_mut() {
+            for script in task.scripts_mut() {
+                script.depends_on.retain(|name| !removed.contains(name));
+            }
+        }
+    }
+
+    /// Catch mistakes that would otherwise only show once the job runs
+    pub fn validate(&self) -> Result<()> {
+        for task in &self.tasks {
+            for script in task.scripts() {
+                if script.name.is_empty() {
+                    return Err(anyhow!("A script in {} has no name", task.name()));
+                }
+                if script.stdin.is_some() && script.delivery == ScriptDelivery::Stdin {
+                    return Err(anyhow!(
+                        "{} is delivered over stdin, it can't also take stdin input",
+                        script.name
+                    ));
+                }
+                if script.script_file.is_some() && !script.script.is_empty() {
+                    return Err(anyhow!("{} sets both script and script_file", script.name));
+                }
+                if let Destination::Group(group) = &script.destination {
+                    return Err(anyhow!(
+                        "{} targets inventory group {group}, pass --inventory to resolve it",
+                        script.name
+                    ));
+                }
+            }
+        }
+        self.dependency_order().map(|_| ())
+    }
+
+    /// Indices of the tasks each task depends on, by the names in its scripts' `depends_on`.
+    /// A name shared by scripts in several tasks depends on all of them.
+    fn dependencies(&self) -> Result<Vec<Vec<usize>>> {
+        self.tasks
+            .iter()
+            .enumerate()
+            .map(|(i, task)| {
+                let mut found = vec![];
+                for name in task.scripts().iter().flat_map(|s| &s.depends_on) {
+                    let matching: Vec<usize> = self
+                        .tasks
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(d, t)| t.scripts().iter().any(|s| &s.name == name).then_some(d))
+                        .collect();
+                    if matching.is_empty() {
+                        return Err(anyhow!("{} depends on unknown script {name}", task.name()));
+                    }
+                    for d in matching {
+                        if d != i && !found.contains(&d) {
+                            found.push(d);
+                        }
+                    }
+                }
+                Ok(found)
+            })
+            .collect()
+    }
+
+    /// Task indices ordered so every task comes after the ones it depends on.
+    /// Fails on unknown names and dependency cycles.
+    pub fn dependency_order(&self) -> Result<Vec<usize>> {
+        let dependencies = self.dependencies()?;
+        let mut waiting: Vec<usize> = dependencies.iter().map(Vec::len).collect();
+        let mut ready: Vec<usize> = (0..self.tasks.len()).filter(|&i| waiting[i] == 0).collect();
+        let mut order = vec![];
+        while let Some(i) = ready.pop() {
+            order.push(i);
+            for (j, ds) in dependencies.iter().enumerate() {
+                if ds.contains(&i) {
+                    waiting[j] -= 1;
+                    if waiting[j] == 0 {
+                        ready.push(j);
+                    }
+                }
+            }
+        }
+        if order.len() < self.tasks.len() {
+            let cycle: Vec<String> = (0..self.tasks.len())
+                .filter(|i| !order.contains(i))
+                .map(|i| self.tasks[i].name())
+                .collect();
+            return Err(anyhow!("Dependency cycle between {}", cycle.join(", ")));
+        }
+        Ok(order)
+    }
+
+    pub fn run(mut self) -> JobRunner {
+        self.apply_destination_defaults();
+        let control = Control {
+            max_parallel: self.max_parallel,
+            ..Control::default()
+        };
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            self.tasks.iter().map(|_| channel(Err(anyhow!("No data")))).unzip();
+        let dependencies = self.dependency_order().and_then(|_| self.dependencies());
+        let runner = JobRunner {
             threads: self
                 .tasks
                 .iter()
-                .map(|t| {
+                .zip(senders)
+                .zip(receivers.clone())
+                .enumerate()
+                .map(|(i, ((t, tx), rx))| {
                     let thread_t = t.clone();
-                    let (tx, rx) = channel(Err(anyhow!("No data")));
-                    std::thread::spawn(move || tx.send(thread_t.run()));
+                    // Tasks in a cycle or waiting on unknown names fail without running
+                    let waits = match &dependencies {
+                        Ok(dependencies) => Ok(dependencies[i]
+                            .iter()
+                            .map(|&d| (self.tasks[d].clone(), receivers[d].clone()))
+                            .collect::<Vec<_>>()),
+                        Err(e) => Err(e.to_string()),
+                    };
+                    let cancel = CancelToken::default();
+                    let control = Control {
+                        cancel: cancel.clone(),
+                        ..control.clone()
+                    };
+                    let progress: Arc<Vec<Progress>> = Arc::new(
+                        t.scripts()
+                            .iter()
+                            .map(|_| Progress {
+                                log_dir: self.log_dir.as_ref().map(PathBuf::from),
+                                ..Progress::default()
+                            })
+                            .collect(),
+                    );
+                    let thread_progress = progress.clone();
+                    let started = Arc::new(Mutex::new(None));
+                    let thread_started = started.clone();
+                    let elapsed = Arc::new(Mutex::new(None));
+                    let thread_elapsed = elapsed.clone();
+                    std::thread::spawn(move || {
+                        let name = thread_t.name();
+                        let result = match waits {
+                            Err(e) => {
+                                tracing::error!("Not running {name}: {e}");
+                                Ok(thread_t.failed(&e))
+                            }
+                            Ok(waits) if !wait_for(&waits, &control.cancel) => {
+                                if control.cancel.is_cancelled() {
+                                    Ok(thread_t.every(|| Cancelled.into()))
+                                } else {
+                                    tracing::info!("Skipping {name}, a dependency didn't succeed");
+                                    Ok(thread_t.skipped())
+                                }
+                            }
+                            Ok(_) => match control.slot() {
+                                Err(_) => Ok(thread_t.every(|| Cancelled.into())),
+                                Ok(_slot) => {
+                                    let started = Instant::now();
+                                    *thread_started.lock().unwrap_or_else(|e| e.into_inner()) =
+                                        Some(started);
+                                    tracing::info!("Starting {name}");
+                                    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                                        thread_t.run(&control, &thread_progress)
+                                    }))
+                                    .unwrap_or_else(|panic| {
+                                        let message = panic_message(&panic);
+                                        tracing::error!("{name} panicked: {message}");
+                                        Ok(thread_t.failed(&message))
+                                    });
+                                    // Before the slot frees, so a queued task can't start within this one's time
+                                    *thread_elapsed.lock().unwrap_or_else(|e| e.into_inner()) =
+                                        Some(started.elapsed());
+                                    result
+                                }
+                            },
+                        };
+                        if let Ok(result) = &result {
+                            match thread_t.status(result) {
+                                status @ Status::Complete => tracing::info!("{name} finished: {status}"),
+                                status => tracing::warn!("{name} finished: {status}"),
+                            }
+                        }
+                        // Tasks that never started took no time
+                        thread_elapsed
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .get_or_insert(Duration::ZERO);
+                        tx.send(result)
+                    });
                     JobThread {
                         task: t.clone(),
                         thread: rx,
+                        progress,
+                        started,
+                        elapsed,
+                        cancel,
                     }
                 })
                 .collect(),
             job: self,
+            control,
+            timed_out: Arc::default(),
+        };
+
+        if let Some(secs) = runner.job.timeout {
+            let watched = runner.clone();
+            let deadline = Instant::now() + Duration::from_secs(secs);
+            std::thread::spawn(move || {
+                while watched.finished() < watched.threads.len() {
+                    if Instant::now() >= deadline {
+                        watched.timed_out.store(true, Ordering::SeqCst);
+                        watched.cancel_all();
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+            });
         }
+        runner
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
+#[allow(clippy::large_enum_variant)]
 pub enum Task {
     Script(Script),
     Serial(Vec<Script>),
+    /// Scripts ran at the same time, reported as one task
+    Parallel(Vec<Script>),
 }
 
 #[derive(Debug)]
 pub enum TaskResult {
     Script(Result<Output>),
     Serial(Vec<Result<Output>>),
+    Parallel(Vec<Result<Output>>),
 }
 
-impl Task {
-    pub fn run(&self) -> Result<TaskResult> {
+impl Clone for TaskResult {
+    fn clone(&self) -> Self {
+        let clone = |rs: &[Result<Output>]| rs.iter().map(clone_result).collect();
         match self {
-            Task::Script(s) => Ok(TaskResult::Script(s.run())),
-            Task::Serial(ss) => Ok(TaskResult::Serial(ss.iter().map(|s| s.run()).collect())),
+            TaskResult::Script(r) => TaskResult::Script(clone_result(r)),
+            TaskResult::Serial(rs) => TaskResult::Serial(clone(rs)),
+            TaskResult::Parallel(rs) => TaskResult::Parallel(clone(rs)),
         }
     }
+}
+
+/// anyhow errors can't be cloned, rebuild the ones statuses are read from
+/// and keep the message of the rest
+fn clone_result(result: &Result<Output>) -> Result<Output> {
+    match result {
+        Ok(output) => Ok(output.clone()),
+        Err(e) if e.is::<Skipped>() => Err(Skipped.into()),
+        Err(e) if e.is::<Cancelled>() => Err(Cancelled.into()),
+        Err(e) if e.is::<ConnectionFailed>() => Err(ConnectionFailed(e.to_string()).into()),
+        Err(e) => match (
+            e.downcast_ref::<TimedOut>(),
+            e.downcast_ref::<DownloadFailed>(),
+            e.downcast_ref::<ShellNotFound>(),
+        ) {
+            (Some(TimedOut(secs)), _, _) => Err(TimedOut(*secs).into()),
+            (_, Some(DownloadFailed { output, error }), _) => Err(DownloadFailed {
+                output: output.clone(),
+                error: anyhow!("{error}"),
+            }
+            .into()),
+            (_, _, Some(ShellNotFound(shell))) => Err(ShellNotFound(shell.clone()).into()),
+            _ => Err(anyhow!("{e}")),
+        },
+    }
+}
 
-    pub fn name(&self) -> String {
+impl TaskResult {
+    /// Every script of `task`, the task this is the result of, succeeded by its own
+    /// success codes, error markers and stderr settings, see [`Task::status`]
+    pub fn is_success(&self, task: &Task) -> bool {
+        task.status(self) == Status::Complete
+    }
+
+    /// Results of every script in the task, in declaration order
+    pub fn outputs(&self) -> &[Result<Output>] {
         match self {
-            Task::Script(s) => s.name.clone(),
-            Task::Serial(ss) => ss
-                .iter()
-                .map(|s| s.name.clone())
-                .collect::<Vec<String>>()
-                .join(" => "),
+            TaskResult::Script(r) => std::slice::from_ref(r),
+            TaskResult::Serial(rs) | TaskResult::Parallel(rs) => rs,
         }
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
-pub enum Destination {
-    /// Run on the machine making the call
-    Local,
-    /// Run on a remote machine via ssh
-    Remote(String),
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// Waiting for its dependencies or for a free slot under `max_parallel`
+    Queued,
+    InProgress,
+    Complete,
+    /// Exited with a non-zero code
+    Failed(i32),
+    /// Terminated by a signal, the number is only known on unix
+    Signaled(Option<i32>),
+    /// The script could not be run at all
+    Error,
+    /// Not ran because an earlier serial step failed
+    Skipped,
+    /// Killed after running past its timeout
+    TimedOut,
+    /// Ran successfully but its artifacts couldn't be downloaded
+    DownloadFailed,
+    /// Never started because the remote couldn't be reached
+    ConnectionFailed,
+    /// Never started because this shell isn't installed
+    ShellNotFound(String),
+    /// Stopped by the user before finishing
+    Cancelled,
+    /// Wrote to stderr while configured to treat that as failure
+    Stderr,
+    /// Succeeded but its output had this many error markers
+    Markers(usize),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
-pub enum Environment {
-    /// Clear out all env variables
-    None,
-    /// Use the current env variables
-    Current,
+/// Error recorded for serial steps after a failure, and tasks whose dependencies failed
+#[derive(Debug)]
+pub struct Skipped;
+
+impl std::fmt::Display for Skipped {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Skipped because an earlier step or dependency failed")
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
-pub enum Shell {
-    Bash,
-    Custom(String),
+impl std::error::Error for Skipped {}
+
+/// Error recorded for scripts killed after their timeout, in seconds
+#[derive(Debug)]
+pub struct TimedOut(pub u64);
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Timed out after {}s", self.0)
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
-pub struct Script {
-    pub name: String,
-    pub destination: Destination,
-    pub environment: Environment,
-    pub shell: Shell,
-    pub script: String,
+impl std::error::Error for TimedOut {}
+
+/// Error recorded when a script succeeded but its artifacts couldn't be downloaded
+#[derive(Debug)]
+pub struct DownloadFailed {
+    pub output: Output,
+    pub error: anyhow::Error,
 }
 
-impl Default for Script {
-    fn default() -> Self {
-        Self {
-            name: "default".into(),
-            destination: Destination::Local,
-            environment: Environment::None,
-            shell: Shell::Bash,
-            script: "bash --version".into(),
-        }
+impl std::fmt::Display for DownloadFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\n{}",
+            self.error,
+            String::from_utf8_lossy(&self.output.stdout)
+        )
     }
 }
 
-impl Script {
-    pub fn run(&self) -> Result<Output> {
-        match &self.destination {
-            Destination::Local => self.run_local(),
-            Destination::Remote(remote) => self.run_remote(&remote),
-        }
-    }
+impl std::error::Error for DownloadFailed {}
 
-    fn run_local(&self) -> Result<Output> {
-        let script = self.write_script()?.into_os_string();
-        Command::new(self.environment.with_shell(&self.shell)?)
-            .arg(script)
-            .output()
-            .map_err(|e| anyhow!("{}", e))
+/// Error recorded when the ssh session to a remote couldn't be opened
+#[derive(Debug)]
+pub struct ConnectionFailed(pub String);
+
+impl std::fmt::Display for ConnectionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
     }
+}
 
-    fn run_remote(&self, remote: &String) -> Result<Output> {
-        let runtime = Runtime::new()?;
+impl std::error::Error for ConnectionFailed {}
 
-        runtime.block_on(async move {
-            let session = Session::connect_mux(remote, KnownHosts::Strict).await?;
-            session
-                .command(self.environment.with_shell(&self.shell)?)
-                .arg(
-                    self.write_remote_script(remote)?
-                        .into_os_string()
-                        .into_string()
-                        .map_err(|_| anyhow!("Failed to stringify path"))?,
-                )
-                .output()
-                .await
-                .map_err(|e| anyhow!("{e}"))
-        })
-    }
+/// Error recorded for scripts stopped by their task being cancelled
+#[derive(Debug)]
+pub struct Cancelled;
 
-    /// Write out a bash script to /tmp for execution
-    fn write_remote_script(&self, remote: &String) -> Result<PathBuf> {
-        let script = self.write_script()?;
-        if Command::new("scp")
-            .arg("-C")
-            .arg(script.clone().into_os_string())
-            .arg(format!("{}:/tmp/", remote))
-            .stderr(Stdio::null())
-            .stdout(Stdio::null())
-            .status()?
-            .success()
-        {
-            let file_name = script.file_name().ok_or(anyhow!("No file_name"))?;
-            let mut remote_path = PathBuf::new();
-            remote_path.push("/tmp");
-            remote_path.push(file_name);
-            Ok(remote_path)
-        } else {
-            Err(anyhow!("Failed to upload script to {remote}"))
-        }
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Cancelled")
     }
+}
 
-    /// Write out a bash script to /tmp for execution
-    fn write_script(&self) -> Result<PathBuf> {
-        let mut path = std::env::temp_dir();
-        path.push(format!("checkmate_{}", self.name));
-        path.set_extension("sh");
+impl std::error::Error for Cancelled {}
 
-        let mut file = File::create(&path).expect("Failed to write script");
+/// Error recorded when the script's shell couldn't be found
+#[derive(Debug)]
+pub struct ShellNotFound(pub String);
 
-        file.write_all(self.script.as_bytes())?;
-        Ok(path)
+impl std::fmt::Display for ShellNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Shell not found: {}", self.0)
     }
 }
 
-impl Environment {
-    fn with_shell(&self, shell: &Shell) -> Result<String> {
-        match self {
-            Environment::None => Ok(shell.path()?),
-            _ => Ok(shell.path()?),
+impl std::error::Error for ShellNotFound {}
+
+/// Write the input to the shell's stdin, if it was piped, then close it
+async fn feed_stdin(stdin: Option<impl AsyncWrite + Unpin>, input: Option<&str>) -> Result<()> {
+    if let (Some(mut stdin), Some(input)) = (stdin, input) {
+        match stdin.write_all(input.as_bytes()).await {
+            // The shell may exit before reading all of the script
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            result => result?,
         }
     }
+    Ok(())
 }
 
-impl Shell {
-    fn path(&self) -> Result<String> {
-        match self {
-            Shell::Bash => Ok("bash".into()),
-            Shell::Custom(x) => Ok(x.clone()),
+/// Unique within this machine for the life of the process
+fn unique_id() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// A local script's own process group, killed if the script is dropped before it emptied
+#[cfg(unix)]
+struct ProcessGroup {
+    id: libc::pid_t,
+    empty: bool,
+}
+
+#[cfg(unix)]
+impl ProcessGroup {
+    /// Make this process the parent of the orphans of any script it runs, so their exit can be
+    /// reaped here rather than waiting on init, which may never reap them in a container
+    fn adopt_orphans() {
+        #[cfg(target_os = "linux")]
+        {
+            static ADOPT: std::sync::Once = std::sync::Once::new();
+            ADOPT.call_once(|| {
+                if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) } != 0 {
+                    tracing::warn!("Failed to become a subreaper: {}", std::io::Error::last_os_error());
+                }
+            });
         }
     }
-}
 
-impl std::fmt::Display for Task {
+    /// Reap the group's processes as they exit, until none is left
+    async fn wait(&mut self) {
+        loop {
+            match unsafe { libc::waitpid(-self.id, std::ptr::null_mut(), libc::WNOHANG) } {
+                reaped if reaped > 0 => continue,
+                // Children in the group still running
+                0 => {}
+                // None of them are ours, any left belong to processes that reap them
+                _ if unsafe { libc::kill(-self.id, 0) } != 0
+                    && std::io::Error::last_os_error().raw_os_error() != Some(libc::EPERM) =>
+                {
+                    break
+                }
+                _ => {}
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        self.empty = true;
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if !self.empty {
+            unsafe { libc::kill(-self.id, libc::SIGKILL) };
+            // Reap the adopted ones as they die, off the runtime since this blocks
+            let id = self.id;
+            std::thread::spawn(move || {
+                while unsafe { libc::waitpid(-id, std::ptr::null_mut(), 0) } > 0 {}
+            });
+        }
+    }
+}
+
+/// Read a stream to the end keeping its last `cap` bytes, noting how many were dropped.
+/// Bytes land in `live` as they arrive so a running script's output can be shown,
+/// and all of them in `log` if there is one.
+async fn read_tail(
+    reader: Option<impl AsyncRead + Unpin>,
+    cap: Option<usize>,
+    live: &Mutex<Vec<u8>>,
+    mut log: Option<File>,
+) -> Result<Vec<u8>> {
+    let Some(mut reader) = reader else {
+        return Ok(vec![]);
+    };
+    let mut dropped = 0;
+    let mut chunk = [0; 8192];
+    loop {
+        let read = reader.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        if let Some(file) = &mut log {
+            if let Err(e) = file.write_all(&chunk[..read]).and_then(|_| file.flush()) {
+                tracing::error!("Failed to write log: {e}");
+                log = None;
+            }
+        }
+        let mut kept = live.lock().unwrap_or_else(|e| e.into_inner());
+        kept.extend_from_slice(&chunk[..read]);
+        // Trimmed once twice the cap so each byte is only moved a few times
+        if let Some(cap) = cap.filter(|&cap| kept.len() > cap.max(chunk.len()) * 2) {
+            let excess = kept.len() - cap;
+            dropped += excess;
+            kept.drain(..excess);
+        }
+    }
+    let mut kept = live.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(cap) = cap.filter(|&cap| kept.len() > cap) {
+        dropped += kept.len() - cap;
+        kept.drain(..kept.len() - cap);
+    }
+    if dropped > 0 {
+        let mut note = format!("[output truncated, {dropped} bytes dropped]\n").into_bytes();
+        note.append(&mut kept);
+        kept = note;
+    }
+    Ok(kept)
+}
+
+/// Block until every dependency has finished, true when they all succeeded.
+/// Gives up once `cancel` is.
+fn wait_for(dependencies: &[(Task, Receiver<Result<TaskResult>>)], cancel: &CancelToken) -> bool {
+    dependencies.iter().all(|(task, rx)| loop {
+        if let Ok(result) = &*rx.borrow() {
+            break task.status(result) == Status::Complete;
+        }
+        if cancel.is_cancelled() {
+            break false;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    })
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("Task panicked: {message}")
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Status::Queued => write!(f, "Queued"),
+            Status::InProgress => write!(f, "In progress"),
+            Status::Complete => write!(f, "Complete"),
+            Status::Failed(code) => write!(f, "Failed (exit {code})"),
+            Status::Signaled(Some(signal)) => write!(f, "Failed (signal {signal})"),
+            Status::Signaled(None) => write!(f, "Failed (signal)"),
+            Status::Error => write!(f, "Failed"),
+            Status::Skipped => write!(f, "Skipped"),
+            Status::TimedOut => write!(f, "Timed out"),
+            Status::DownloadFailed => write!(f, "Failed (download)"),
+            Status::ConnectionFailed => write!(f, "Connection failed"),
+            Status::ShellNotFound(shell) => write!(f, "Shell not found: {shell}"),
+            Status::Cancelled => write!(f, "Cancelled"),
+            Status::Stderr => write!(f, "Failed (stderr)"),
+            Status::Markers(count) => write!(f, "Failed ({count} errors in output)"),
+        }
+    }
+}
+
+impl Task {
+    /// Run every script, none are started while `control` is paused or their mutex key is held.
+    /// `progress` holds counters per script, see [`Script::run_counted`].
+    pub fn run(&self, control: &Control, progress: &[Progress]) -> Result<TaskResult> {
+        match self {
+            Task::Script(s) => Ok(TaskResult::Script(control.run(s, &progress[0]))),
+            Task::Serial(ss) => {
+                let mut failed = false;
+                Ok(TaskResult::Serial(
+                    ss.iter()
+                        .zip(progress)
+                        .map(|(s, progress)| {
+                            if failed {
+                                return Err(Skipped.into());
+                            }
+                            let result = control.run(s, progress);
+                            failed = s.status(&result) != Status::Complete;
+                            result
+                        })
+                        .collect(),
+                ))
+            }
+            Task::Parallel(ss) => Ok(TaskResult::Parallel(std::thread::scope(|scope| {
+                let handles: Vec<_> = ss
+                    .iter()
+                    .zip(progress)
+                    .map(|(s, progress)| scope.spawn(|| control.run(s, progress)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|h| h.join().unwrap_or_else(|_| Err(anyhow!("Script panicked"))))
+                    .collect()
+            }))),
+        }
+    }
+
+    /// A single local bash script
+    ///
+    /// ```
+    /// use checkmate::{Control, Progress, Status, Task};
+    ///
+    /// let task = Task::local("greet", "echo hello");
+    /// let result = task.run(&Control::default(), &[Progress::default()]).unwrap();
+    /// assert_eq!(task.status(&result), Status::Complete);
+    /// ```
+    pub fn local(name: impl Into<String>, script: impl Into<String>) -> Self {
+        Task::Script(Script::new(name, script))
+    }
+
+    /// A result failing every script with `message`, for when the task couldn't run
+    pub fn failed(&self, message: &str) -> TaskResult {
+        self.every(|| anyhow!("{message}"))
+    }
+
+    /// A result skipping every script, for when a dependency didn't succeed
+    pub fn skipped(&self) -> TaskResult {
+        self.every(|| Skipped.into())
+    }
+
+    fn every(&self, error: impl Fn() -> anyhow::Error) -> TaskResult {
+        let errors = || self.scripts().iter().map(|_| Err(error())).collect();
+        match self {
+            Task::Script(_) => TaskResult::Script(Err(error())),
+            Task::Serial(_) => TaskResult::Serial(errors()),
+            Task::Parallel(_) => TaskResult::Parallel(errors()),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Task::Script(s) => s.name.clone(),
+            Task::Serial(ss) => ss
+                .iter()
+                .map(|s| s.name.clone())
+                .collect::<Vec<String>>()
+                .join(" => "),
+            Task::Parallel(ss) => ss
+                .iter()
+                .map(|s| s.name.clone())
+                .collect::<Vec<String>>()
+                .join(" || "),
+        }
+    }
+
+    /// Every script in the task, in declaration order
+    pub fn scripts(&self) -> &[Script] {
+        match self {
+            Task::Script(s) => std::slice::from_ref(s),
+            Task::Serial(ss) | Task::Parallel(ss) => ss,
+        }
+    }
+
+    /// Tags of every script in the task
+    pub fn tags(&self) -> impl Iterator<Item = &String> {
+        self.scripts().iter().flat_map(|s| &s.tags)
+    }
+
+    pub fn scripts_mut(&mut self) -> &mut [Script] {
+        match self {
+            Task::Script(s) => std::slice::from_mut(s),
+            Task::Serial(ss) | Task::Parallel(ss) => ss,
+        }
+    }
+
+    /// Overall status of a finished task, reports the first unsuccessful script
+    pub fn status(&self, result: &TaskResult) -> Status {
+        self.scripts()
+            .iter()
+            .zip(result.outputs())
+            .map(|(s, r)| s.status(r))
+            .find(|s| *s != Status::Complete)
+            .unwrap_or(Status::Complete)
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Task::Script(_) => "Script",
+            Task::Serial(_) => "Serial",
+            Task::Parallel(_) => "Parallel",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, StaticType, JsonSchema)]
+pub enum Destination {
+    /// Run on the machine making the call
+    Local,
+    /// Run on a remote machine via ssh
+    Remote(Remote),
+    /// Run on every host of an inventory group
+    Group(String),
+}
+
+/// An ssh host, also accepts the plain `user@host` string form
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, StaticType)]
+#[serde(from = "RemoteRepr")]
+pub struct Remote {
+    pub host: String,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    /// Path to the private key to authenticate with
+    pub identity_file: Option<String>,
+    /// Compress the ssh session, unset keeps ssh's default
+    pub compression: Option<bool>,
+    /// Cipher for the ssh session, e.g. aes128-gcm@openssh.com
+    pub cipher: Option<String>,
+    /// Seconds to wait for the ssh connection, separate from the script's timeout
+    pub connect_timeout: Option<u64>,
+    /// Directory scripts are uploaded to and ran from, /tmp unless set
+    pub tmp_dir: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum RemoteRepr {
+    /// `user@host`
+    Short(String),
+    Full {
+        host: String,
+        port: Option<u16>,
+        user: Option<String>,
+        /// Path to the private key to authenticate with
+        identity_file: Option<String>,
+        /// Compress the ssh session, unset keeps ssh's default
+        compression: Option<bool>,
+        /// Cipher for the ssh session, e.g. aes128-gcm@openssh.com
+        cipher: Option<String>,
+        /// Seconds to wait for the ssh connection, separate from the script's timeout
+        connect_timeout: Option<u64>,
+        /// Directory scripts are uploaded to and ran from, /tmp unless set
+        tmp_dir: Option<String>,
+    },
+}
+
+impl JsonSchema for Remote {
+    fn schema_name() -> String {
+        "Remote".into()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        RemoteRepr::json_schema(gen)
+    }
+}
+
+impl From<RemoteRepr> for Remote {
+    fn from(repr: RemoteRepr) -> Self {
+        match repr {
+            RemoteRepr::Short(s) => s.as_str().into(),
+            RemoteRepr::Full {
+                host,
+                port,
+                user,
+                identity_file,
+                compression,
+                cipher,
+                connect_timeout,
+                tmp_dir,
+            } => Remote {
+                host,
+                port,
+                user,
+                identity_file,
+                compression,
+                cipher,
+                connect_timeout,
+                tmp_dir,
+            },
+        }
+    }
+}
+
+impl From<&str> for Remote {
+    fn from(s: &str) -> Self {
+        let (user, host) = match s.split_once('@') {
+            Some((user, host)) => (Some(user.to_string()), host.to_string()),
+            None => (None, s.to_string()),
+        };
+        Remote {
+            host,
+            port: None,
+            user,
+            identity_file: None,
+            compression: None,
+            cipher: None,
+            connect_timeout: None,
+            tmp_dir: None,
+        }
+    }
+}
+
+impl Remote {
+    /// Path of a script file in the remote's temp directory
+    fn script_path(&self, file_name: &str) -> String {
+        let dir = self.tmp_dir.as_deref().unwrap_or("/tmp");
+        format!("{}/{file_name}", dir.trim_end_matches('/'))
+    }
+
+    fn session_builder(&self, known_hosts: &KnownHostsPolicy) -> SessionBuilder {
+        let mut builder = SessionBuilder::default();
+        builder.known_hosts_check(known_hosts.into());
+        if let Some(port) = self.port {
+            builder.port(port);
+        }
+        if let Some(user) = &self.user {
+            builder.user(user.clone());
+        }
+        if let Some(identity_file) = &self.identity_file {
+            builder.keyfile(identity_file);
+        }
+        if let Some(compression) = self.compression {
+            builder.compression(compression);
+        }
+        if let Some(secs) = self.connect_timeout {
+            builder.connect_timeout(Duration::from_secs(secs));
+        }
+        builder
+    }
+
+    /// The session builder has no cipher option, so select it in an ssh config file.
+    /// Passing a config file skips the default ones, so it includes the user's and the
+    /// system's own after the cipher, in the order ssh reads them.
+    fn cipher_config(cipher: &str) -> Result<PathBuf> {
+        let path = std::env::temp_dir().join(format!("checkmate_ssh_{}.conf", unique_id()));
+        std::fs::write(
+            &path,
+            format!("Ciphers {cipher}\nInclude ~/.ssh/config\nInclude /etc/ssh/ssh_config\n"),
+        )
+        .map_err(|e| anyhow!("Failed to write ssh config {}: {e}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Open a session, bounded by the connect timeout when one is set
+    async fn connect(&self, known_hosts: &KnownHostsPolicy) -> Result<Session> {
+        let mut builder = self.session_builder(known_hosts);
+        let config = self.cipher.as_deref().map(Self::cipher_config).transpose()?;
+        if let Some(path) = &config {
+            builder.config_file(path);
+        }
+        let connect = async {
+            let connect = builder.connect_mux(&self.host);
+            match self.connect_timeout {
+                Some(secs) => tokio::time::timeout(Duration::from_secs(secs), connect)
+                    .await
+                    .map_err(|_| {
+                        ConnectionFailed(format!("Connection to {self} timed out after {secs}s"))
+                    })?,
+                None => connect.await,
+            }
+            .map_err(|e| self.connect_error(e, std::env::var_os("PATH").as_deref()))
+        };
+        let connected = connect.await;
+        // Only read while connecting, commands go over the open connection
+        if let Some(path) = &config {
+            let _ = std::fs::remove_file(path);
+        }
+        connected
+    }
+
+    /// Tell a missing ssh binary, searched for in `path`, apart from a failed connection
+    fn connect_error(&self, error: openssh::Error, path: Option<&std::ffi::OsStr>) -> anyhow::Error {
+        let not_found = matches!(&error, openssh::Error::Connect(e) if e.kind() == std::io::ErrorKind::NotFound);
+        let on_path = path.is_some_and(|path| std::env::split_paths(path).any(|dir| dir.join("ssh").is_file()));
+        let missing = not_found && !on_path;
+        match missing {
+            true => ConnectionFailed("ssh not found; install openssh-client".into()).into(),
+            false => ConnectionFailed(format!("Failed to connect to {self}: {error}")).into(),
+        }
+    }
+
+    /// `[user@]host` as understood by ssh
+    fn ssh_host(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Options for an ssh command line matching the session's settings
+    fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(port) = self.port {
+            args.push(format!("-p {port}"));
+        }
+        if let Some(identity_file) = &self.identity_file {
+            args.push(format!("-i {identity_file}"));
+        }
+        match self.compression {
+            Some(true) => args.push("-C".into()),
+            Some(false) => args.push("-o Compression=no".into()),
+            None => {}
+        }
+        if let Some(cipher) = &self.cipher {
+            args.push(format!("-c {cipher}"));
+        }
+        if let Some(secs) = self.connect_timeout {
+            args.push(format!("-o ConnectTimeout={secs}"));
+        }
+        args
+    }
+}
+
+impl std::fmt::Display for Remote {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.ssh_host())?;
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Destination::Local => write!(f, "Local"),
+            Destination::Remote(remote) => write!(f, "Remote({remote})"),
+            Destination::Group(group) => write!(f, "Group({group})"),
+        }
+    }
+}
+
+/// How ssh treats hosts missing from known_hosts
+#[derive(Clone, Debug, Default, Serialize, Deserialize, StaticType, JsonSchema)]
+pub enum KnownHostsPolicy {
+    /// Refuse to connect to unknown hosts
+    #[default]
+    Strict,
+    /// Add unknown hosts to known_hosts
+    Add,
+    /// Connect to unknown hosts without recording them
+    Accept,
+}
+
+impl From<&KnownHostsPolicy> for KnownHosts {
+    fn from(policy: &KnownHostsPolicy) -> Self {
+        match policy {
+            KnownHostsPolicy::Strict => KnownHosts::Strict,
+            KnownHostsPolicy::Add => KnownHosts::Add,
+            KnownHostsPolicy::Accept => KnownHosts::Accept,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
+pub enum Environment {
+    /// Clear out all env variables
+    None,
+    /// Use the current env variables
+    Current,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    /// POSIX sh
+    Sh,
+    Fish,
+    /// `powershell` on Windows, `pwsh` elsewhere
+    PowerShell,
+    Custom(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
+pub struct Script {
+    pub name: String,
+    pub destination: Destination,
+    pub environment: Environment,
+    pub shell: Shell,
+    #[serde(default)]
+    pub script: String,
+    /// Path of a script on disk to run instead of `script`, read each time the script runs.
+    /// Relative paths resolve against the directory checkmate was started in.
+    pub script_file: Option<String>,
+    /// Exit codes that count as success
+    #[serde(default = "default_success_codes")]
+    pub success_codes: Vec<i32>,
+    /// Permissions for the uploaded script file, e.g. 0o700
+    pub script_mode: Option<u32>,
+    /// Seconds to wait before killing the script
+    pub timeout: Option<u64>,
+    /// Command ran by a bash `trap` when the script exits, even when killed
+    pub exit_trap: Option<String>,
+    /// Host key checking for remote destinations
+    #[serde(default)]
+    pub known_hosts: KnownHostsPolicy,
+    /// Files copied back from a remote destination after the script succeeds
+    #[serde(default)]
+    pub download: Vec<Artifact>,
+    /// Treat any output on stderr as a failure, whatever the exit code
+    #[serde(default)]
+    pub fail_on_stderr: bool,
+    /// How the script reaches the shell
+    #[serde(default)]
+    pub delivery: ScriptDelivery,
+    /// Output lines containing any of these are counted as errors, e.g. "FATAL"
+    #[serde(default)]
+    pub error_markers: Vec<String>,
+    /// Fail when any error marker is found, even if the exit code was a success
+    #[serde(default)]
+    pub markers_fail: bool,
+    /// Times to rerun the script after an unsuccessful attempt
+    #[serde(default)]
+    pub retries: u32,
+    /// Seconds to wait between attempts
+    pub retry_delay: Option<u64>,
+    /// Scripts sharing a mutex key never run at the same time, e.g. "database"
+    pub mutex: Option<String>,
+    /// Runs before the measured one whose output and status are discarded
+    #[serde(default)]
+    pub warmup: u32,
+    /// Wait for commands the script put in the background before finishing.
+    /// Locally the script gets its own process group and the task waits until every process
+    /// in it has exited, remote scripts `wait` for their shell's jobs, which is skipped when
+    /// the script calls `exit` itself. A daemon started this way keeps the task running
+    /// until it exits or times out.
+    #[serde(default)]
+    pub wait_for_children: bool,
+    /// Scripts in other tasks that must succeed before this task starts
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Directory the script runs in, relative paths in the script resolve against it
+    pub working_dir: Option<String>,
+    /// Piped to the script's stdin, which is closed after it
+    pub stdin: Option<String>,
+    /// Send stderr to stdout like `2>&1`, keeping the two in order
+    #[serde(default)]
+    pub merge_stderr: bool,
+    /// Keep only the last this many bytes of stdout and of stderr
+    pub max_output_bytes: Option<u64>,
+    /// Replace `${VAR}` in the script with the variable from the environment before it runs.
+    /// A variable that isn't set is an error rather than an empty string.
+    #[serde(default)]
+    pub interpolate: bool,
+    /// Run the shell through `sudo -n`. There's no terminal to type a password into,
+    /// so the user needs passwordless sudo on the destination.
+    #[serde(default)]
+    pub sudo: bool,
+    /// Labels to pick tasks by, e.g. "lint" or "deploy"
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, StaticType, JsonSchema)]
+pub enum ScriptDelivery {
+    /// Write the script to the temp dir and pass its path to the shell
+    #[default]
+    TempFile,
+    /// Pipe the script to the shell's stdin without writing a file
+    Stdin,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, StaticType, JsonSchema)]
+pub struct Artifact {
+    /// Path on the remote machine
+    pub remote: String,
+    /// Where to put it locally
+    pub local: String,
+}
+
+fn default_success_codes() -> Vec<i32> {
+    vec![0]
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self {
+            name: "default".into(),
+            destination: Destination::Local,
+            environment: Environment::None,
+            shell: Shell::Bash,
+            script: "bash --version".into(),
+            script_file: None,
+            success_codes: default_success_codes(),
+            script_mode: None,
+            timeout: None,
+            exit_trap: None,
+            known_hosts: KnownHostsPolicy::default(),
+            download: vec![],
+            fail_on_stderr: false,
+            delivery: ScriptDelivery::default(),
+            error_markers: vec![],
+            markers_fail: false,
+            retries: 0,
+            retry_delay: None,
+            mutex: None,
+            warmup: 0,
+            wait_for_children: false,
+            depends_on: vec![],
+            working_dir: None,
+            stdin: None,
+            merge_stderr: false,
+            max_output_bytes: None,
+            interpolate: false,
+            sudo: false,
+            tags: vec![],
+        }
+    }
+}
+
+/// Builds a [`Script`], settings left unset keep their defaults
+#[derive(Clone, Debug)]
+pub struct ScriptBuilder {
+    script: Script,
+}
+
+impl ScriptBuilder {
+    pub fn destination(mut self, destination: Destination) -> Self {
+        self.script.destination = destination;
+        self
+    }
+
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.script.environment = environment;
+        self
+    }
+
+    pub fn shell(mut self, shell: Shell) -> Self {
+        self.script.shell = shell;
+        self
+    }
+
+    /// Seconds to wait before killing the script
+    pub fn timeout(mut self, secs: u64) -> Self {
+        self.script.timeout = Some(secs);
+        self
+    }
+
+    pub fn build(self) -> Script {
+        self.script
+    }
+}
+
+impl Script {
+    /// A local bash script, see [`Script::builder`] to set more
+    ///
+    /// ```
+    /// let output = checkmate::Script::new("greet", "echo hello").run().unwrap();
+    /// assert_eq!(output.stdout, b"hello\n");
+    /// ```
+    pub fn new(name: impl Into<String>, script: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            script: script.into(),
+            ..Default::default()
+        }
+    }
+
+    /// ```
+    /// use checkmate::{Environment, Script, Shell};
+    ///
+    /// let script = Script::builder("home", "echo \"$HOME\"")
+    ///     .environment(Environment::Current)
+    ///     .shell(Shell::Sh)
+    ///     .timeout(5)
+    ///     .build();
+    /// let output = script.run().unwrap();
+    /// assert_eq!(output.stdout, format!("{}\n", std::env::var("HOME").unwrap()).into_bytes());
+    /// ```
+    pub fn builder(name: impl Into<String>, script: impl Into<String>) -> ScriptBuilder {
+        ScriptBuilder {
+            script: Self::new(name, script),
+        }
+    }
+
+    pub fn run(&self) -> Result<Output> {
+        self.run_counted(&Progress::default(), &Control::default())
+    }
+
+    /// Run `warmup` times discarding the results, then for real, retrying
+    /// unsuccessful attempts up to `retries` times.
+    /// `progress` is incremented as each run starts. Remotes connect through `control`'s
+    /// sessions, and the script is killed once its cancel token is.
+    pub fn run_counted(&self, progress: &Progress, control: &Control) -> Result<Output> {
+        for _ in 0..self.warmup {
+            if control.cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            progress.warmups.fetch_add(1, Ordering::SeqCst);
+            let _ = self.run_once(progress, control);
+        }
+        loop {
+            if control.cancel.is_cancelled() {
+                return Err(Cancelled.into());
+            }
+            let attempt = progress.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            let result = self.run_once(progress, control);
+            let status = self.status(&result);
+            if attempt > self.retries || status == Status::Complete {
+                return result;
+            }
+            tracing::info!("Retrying {} after attempt {attempt}: {status}", self.name);
+            if let Some(secs) = self.retry_delay {
+                std::thread::sleep(Duration::from_secs(secs));
+            }
+        }
+    }
+
+    fn run_once(&self, progress: &Progress, control: &Control) -> Result<Output> {
+        progress.clear();
+        tracing::debug!("Running {}: {}", self.name, self.resolved_command());
+        let output = match &self.destination {
+            Destination::Local => self.run_local(progress, &control.cancel),
+            Destination::Remote(remote) => self.run_remote(remote, progress, control),
+            Destination::Group(group) => Err(anyhow!(
+                "Group {group} must be resolved against an inventory before running"
+            )),
+        }?;
+        // sudo -n exits 1 without running anything when it would have prompted
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if self.sudo
+            && output.status.code() == Some(1)
+            && stderr.starts_with("sudo:")
+            && stderr.contains("password is required")
+        {
+            return Err(anyhow!(
+                "sudo asked for a password on {}, the script needs passwordless sudo",
+                self.destination
+            ));
+        }
+        Ok(output)
+    }
+
+    /// Program to spawn and its leading arguments, the shell or sudo running the shell.
+    /// `env` goes through `env` in front of the shell, for sudo and ssh which don't pass
+    /// on the caller's variables.
+    fn program(&self, env: &[(String, String)]) -> Result<(String, Vec<String>)> {
+        let shell = self.environment.with_shell(&self.shell)?;
+        let mut args: Vec<String> = match env.is_empty() {
+            true => vec![],
+            false => std::iter::once("env".into())
+                .chain(env.iter().map(|(name, value)| format!("{name}={value}")))
+                .collect(),
+        };
+        args.push(shell);
+        match self.sudo {
+            true => Ok(("sudo".into(), [vec!["-n".into()], args].concat())),
+            false => Ok((args.remove(0), args)),
+        }
+    }
+
+    /// Status of a finished run of this script
+    pub fn status(&self, result: &Result<Output>) -> Status {
+        match result {
+            Ok(output) if self.fail_on_stderr && !output.stderr.is_empty() => Status::Stderr,
+            Ok(output) => match output.status.code() {
+                Some(code) if self.success_codes.contains(&code) => {
+                    match self.markers(output).len() {
+                        count if self.markers_fail && count > 0 => Status::Markers(count),
+                        _ => Status::Complete,
+                    }
+                }
+                Some(code) => Status::Failed(code),
+                None => Status::Signaled(signal(&output.status)),
+            },
+            Err(e) if e.is::<Skipped>() => Status::Skipped,
+            Err(e) if e.is::<TimedOut>() => Status::TimedOut,
+            Err(e) if e.is::<DownloadFailed>() => Status::DownloadFailed,
+            Err(e) if e.is::<ConnectionFailed>() => Status::ConnectionFailed,
+            Err(e) if e.is::<Cancelled>() => Status::Cancelled,
+            Err(e) => match e.downcast_ref::<ShellNotFound>() {
+                Some(ShellNotFound(shell)) => Status::ShellNotFound(shell.clone()),
+                None => Status::Error,
+            },
+        }
+    }
+
+    /// The command line the script is ran with, the temp file is left as a placeholder
+    pub fn resolved_command(&self) -> String {
+        let shell = self.environment.with_shell(&self.shell).unwrap_or_default();
+        let shell = match self.sudo {
+            true => format!("sudo -n {shell}"),
+            false => shell,
+        };
+        let shell = match self.delivery {
+            ScriptDelivery::TempFile => [&[shell.as_str()], self.shell.file_args()].concat(),
+            ScriptDelivery::Stdin => [&[shell.as_str()], self.shell.stdin_args()].concat(),
+        }
+        .join(" ");
+        let invocation = match (&self.delivery, &self.destination) {
+            (ScriptDelivery::TempFile, Destination::Local) => {
+                format!("{shell} {}", self.script_path("<id>").display())
+            }
+            (ScriptDelivery::TempFile, Destination::Remote(remote)) => {
+                format!("{shell} {}", remote.script_path(&self.script_file_name("<id>")))
+            }
+            (ScriptDelivery::TempFile, _) => {
+                format!("{shell} /tmp/{}", self.script_file_name("<id>"))
+            }
+            (ScriptDelivery::Stdin, _) => format!("{shell} < <script>"),
+        };
+        let invocation = match &self.stdin {
+            Some(_) if self.delivery == ScriptDelivery::TempFile => format!("{invocation} < <stdin>"),
+            _ => invocation,
+        };
+        match &self.destination {
+            Destination::Local => invocation,
+            Destination::Remote(remote) => {
+                let mut args = remote.ssh_args();
+                args.push(remote.ssh_host());
+                format!("ssh {} {invocation}", args.join(" "))
+            }
+            Destination::Group(group) => format!("ssh <each host in {group}> {invocation}"),
+        }
+    }
+
+    /// Every step a run would take, without taking any of them
+    pub fn plan(&self) -> Vec<String> {
+        let mut steps = vec![];
+        if let Some(file) = &self.script_file {
+            steps.push(format!("read {file}"));
+        }
+        let local_file = self.script_path("<id>");
+        let remote_file = |remote: &Remote| remote.script_path(&self.script_file_name("<id>"));
+        let ssh = |remote: &Remote| {
+            let mut args = remote.ssh_args();
+            args.push(remote.ssh_host());
+            format!("ssh {}", args.join(" "))
+        };
+
+        match (&self.delivery, &self.destination) {
+            (ScriptDelivery::TempFile, Destination::Remote(remote)) => {
+                let remote_file = remote_file(remote);
+                steps.push(format!("{} cat > {remote_file} < <script>", ssh(remote)));
+                if let Some(mode) = self.script_mode {
+                    steps.push(format!("{} chmod {mode:o} {remote_file}", ssh(remote)));
+                }
+            }
+            (ScriptDelivery::TempFile, _) => {
+                steps.push(format!("write {}", local_file.display()));
+            }
+            (ScriptDelivery::Stdin, _) => {}
+        }
+
+        let environment = match self.environment {
+            Environment::None => "clean environment",
+            Environment::Current => "current environment",
+        };
+        if let Some(dir) = &self.working_dir {
+            steps.push(format!("cd {dir}"));
+        }
+        steps.push(format!("{} ({environment})", self.resolved_command()));
+
+        if let (ScriptDelivery::TempFile, Destination::Remote(remote)) =
+            (&self.delivery, &self.destination)
+        {
+            steps.push(format!("{} rm -f {}", ssh(remote), remote_file(remote)));
+            for artifact in &self.download {
+                steps.push(format!(
+                    "{} cat {} > {}",
+                    ssh(remote),
+                    artifact.remote,
+                    artifact.local
+                ));
+            }
+        }
+        steps
+    }
+
+    /// Lines of stdout and stderr containing an error marker
+    pub fn markers(&self, output: &Output) -> Vec<String> {
+        if self.error_markers.is_empty() {
+            return vec![];
+        }
+        [&output.stdout, &output.stderr]
+            .iter()
+            .flat_map(|bytes| {
+                String::from_utf8_lossy(bytes)
+                    .lines()
+                    .filter(|l| self.error_markers.iter().any(|m| l.contains(m.as_str())))
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(skip_all, fields(script = %self.name), err)]
+    fn run_local(&self, progress: &Progress, cancel: &CancelToken) -> Result<Output> {
+        let (program, prefix) = self.program(if self.sudo { &progress.env } else { &[] })?;
+        let mut command = tokio::process::Command::new(program);
+        command.args(prefix);
+        command.envs(progress.env.iter().cloned());
+        #[cfg(unix)]
+        if self.wait_for_children {
+            ProcessGroup::adopt_orphans();
+            // SAFETY: setpgid is async-signal-safe
+            unsafe {
+                command.pre_exec(|| match libc::setpgid(0, 0) {
+                    0 => Ok(()),
+                    _ => Err(std::io::Error::last_os_error()),
+                });
+            }
+        }
+        if let Some(dir) = &self.working_dir {
+            if !Path::new(dir).is_dir() {
+                return Err(anyhow!("Working directory {dir} does not exist"));
+            }
+            command.current_dir(dir);
+        }
+        let script = match self.delivery {
+            ScriptDelivery::TempFile => Some(self.write_script(&progress.env)?),
+            ScriptDelivery::Stdin => None,
+        };
+        match &script {
+            Some(path) => command.args(self.shell.file_args()).arg(path),
+            None => command.args(self.shell.stdin_args()),
+        };
+        let input = self.input(&progress.env)?;
+        match input {
+            Some(_) => command.stdin(Stdio::piped()),
+            None => command.stdin(Stdio::null()),
+        };
+        command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let runtime = Runtime::new()?;
+
+        runtime.block_on(async move {
+            let output = async {
+                let mut child = command.spawn().map_err(|e| match e.kind() {
+                    std::io::ErrorKind::NotFound => {
+                        ShellNotFound(command.as_std().get_program().to_string_lossy().into()).into()
+                    }
+                    _ => anyhow::Error::from(e),
+                })?;
+                tracing::debug!(pid = ?child.id(), "spawned {}", self.resolved_command());
+                let (stdin, stdout, stderr) = (child.stdin.take(), child.stdout.take(), child.stderr.take());
+                #[cfg(unix)]
+                let mut group = child
+                    .id()
+                    .filter(|_| self.wait_for_children)
+                    .map(|id| ProcessGroup { id: id as libc::pid_t, empty: false });
+                let status = async {
+                    let status = child.wait().await;
+                    #[cfg(unix)]
+                    if let Some(group) = &mut group {
+                        group.wait().await;
+                    }
+                    status
+                };
+                let (written, output) = tokio::join!(
+                    feed_stdin(stdin, input.as_deref()),
+                    self.capture(stdout, stderr, status, progress)
+                );
+                written?;
+                output
+            };
+            let output = self.with_timeout(output, cancel).await;
+            if let Some(path) = &script {
+                let _ = std::fs::remove_file(path);
+            }
+            let output = output??;
+            tracing::debug!("complete, {}", output.status);
+            Ok(output)
+        })
+    }
+
+    /// Read both streams to the end then wait for the exit status.
+    /// Past `max_output_bytes` the start of a stream is dropped and a note put in its place.
+    async fn capture<E>(
+        &self,
+        stdout: Option<impl AsyncRead + Unpin>,
+        stderr: Option<impl AsyncRead + Unpin>,
+        status: impl Future<Output = std::result::Result<ExitStatus, E>>,
+        progress: &Progress,
+    ) -> Result<Output>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let cap = self.max_output_bytes.map(|cap| cap as usize);
+        let [stdout_log, stderr_log] = progress.log_files(&self.name);
+        let (stdout, stderr) = tokio::join!(
+            read_tail(stdout, cap, &progress.stdout, stdout_log),
+            read_tail(stderr, cap, &progress.stderr, stderr_log)
+        );
+        Ok(Output {
+            status: status.await?,
+            stdout: stdout?,
+            stderr: stderr?,
+        })
+    }
+
+    /// What the shell reads from stdin: the script itself or the configured input
+    fn input(&self, env: &[(String, String)]) -> Result<Option<String>> {
+        match self.delivery {
+            ScriptDelivery::Stdin => Ok(Some(self.body(env)?)),
+            ScriptDelivery::TempFile => Ok(self.stdin.clone()),
+        }
+    }
+
+    /// Bound a future by the script's timeout and `cancel`, dropping it once either hits
+    async fn with_timeout<T>(&self, future: impl Future<Output = T>, cancel: &CancelToken) -> Result<T> {
+        let bounded = async {
+            match self.timeout {
+                Some(secs) => tokio::time::timeout(Duration::from_secs(secs), future)
+                    .await
+                    .map_err(|_| TimedOut(secs).into()),
+                None => Ok(future.await),
+            }
+        };
+        tokio::select! {
+            result = bounded => result,
+            _ = cancel.cancelled() => Err(Cancelled.into()),
+        }
+    }
+
+    #[tracing::instrument(skip_all, fields(script = %self.name, remote = %remote), err)]
+    fn run_remote(&self, remote: &Remote, progress: &Progress, control: &Control) -> Result<Output> {
+        let runtime = Runtime::new()?;
+
+        runtime.block_on(async move {
+            let session = control.sessions.get(remote, &self.known_hosts).await?;
+            let remote_script = match self.delivery {
+                ScriptDelivery::TempFile => Some(self.write_remote_script(&session, remote, &progress.env).await?),
+                ScriptDelivery::Stdin => None,
+            };
+            let (program, prefix) = self.program(&progress.env)?;
+            let mut command = session.command(program);
+            command.args(prefix);
+            match &remote_script {
+                Some(path) => command.args(self.shell.file_args()).arg(path),
+                None => command.args(self.shell.stdin_args()),
+            };
+            let input = self.input(&progress.env)?;
+            match input {
+                Some(_) => command.stdin(openssh::Stdio::piped()),
+                None => command.stdin(openssh::Stdio::null()),
+            };
+            command
+                .stdout(openssh::Stdio::piped())
+                .stderr(openssh::Stdio::piped());
+            let output = async {
+                let mut child = command.spawn().await?;
+                tracing::debug!("spawned {}", self.resolved_command());
+                let (stdin, stdout, stderr) =
+                    (child.stdin().take(), child.stdout().take(), child.stderr().take());
+                let (written, output) = tokio::join!(
+                    feed_stdin(stdin, input.as_deref()),
+                    self.capture(stdout, stderr, child.wait(), progress)
+                );
+                written?;
+                output
+            };
+            let output = self.with_timeout(output, &control.cancel).await;
+
+            if let Some(path) = &remote_script {
+                if let Err(e) = session.command("rm").arg("-f").arg(path).status().await {
+                    tracing::warn!("Failed to remove {path} on {remote}: {e}");
+                }
+            }
+            let output = output??;
+            tracing::debug!("complete, {}", output.status);
+
+            if self.status(&Ok(output.clone())) == Status::Complete {
+                if let Err(error) = self.download_artifacts(&session, remote).await {
+                    return Err(DownloadFailed { output, error }.into());
+                }
+            }
+            Ok(output)
+        })
+    }
+
+    /// Copy each artifact back over the session once the script has succeeded,
+    /// so downloads share its connection and host key checks
+    async fn download_artifacts(&self, session: &Session, remote: &Remote) -> Result<()> {
+        for artifact in &self.download {
+            let failed = |e: &dyn std::fmt::Display| {
+                anyhow!("Failed to download {} from {remote}: {e}", artifact.remote)
+            };
+            let mut child = session
+                .command("cat")
+                .arg(&artifact.remote)
+                .stdin(openssh::Stdio::null())
+                .stdout(openssh::Stdio::piped())
+                .stderr(openssh::Stdio::piped())
+                .spawn()
+                .await
+                .map_err(|e| failed(&e))?;
+            let mut file = File::create(&artifact.local).map_err(|e| failed(&e))?;
+            let mut stdout = child.stdout().take().expect("stdout is piped");
+            let mut chunk = [0; 8192];
+            let copied: Result<()> = async {
+                loop {
+                    let read = stdout.read(&mut chunk).await?;
+                    if read == 0 {
+                        return Ok(());
+                    }
+                    file.write_all(&chunk[..read])?;
+                }
+            }
+            .await;
+            let output = child.wait_with_output().await.map_err(|e| failed(&e))?;
+            let error = match copied {
+                Err(e) => Some(e.to_string()),
+                Ok(()) if !output.status.success() => {
+                    Some(String::from_utf8_lossy(&output.stderr).trim().to_string())
+                }
+                Ok(()) => None,
+            };
+            if let Some(error) = error {
+                drop(file);
+                let _ = std::fs::remove_file(&artifact.local);
+                return Err(failed(&error));
+            }
+        }
+        Ok(())
+    }
+
+    /// Upload the script to /tmp over the session, so it shares the connection
+    /// and authentication used to run it
+    async fn write_remote_script(
+        &self,
+        session: &Session,
+        remote: &Remote,
+        env: &[(String, String)],
+    ) -> Result<String> {
+        let path = remote.script_path(&self.script_file_name(&unique_id()));
+        let quoted = format!("'{}'", path.replace('\'', r"'\''"));
+        let upload = match self.script_mode {
+            Some(mode) => format!("cat > {quoted} && chmod {mode:o} {quoted}"),
+            None => format!("cat > {quoted}"),
+        };
+
+        let mut child = session
+            .shell(upload)
+            .stdin(openssh::Stdio::piped())
+            .stdout(openssh::Stdio::null())
+            .stderr(openssh::Stdio::piped())
+            .spawn()
+            .await?;
+        let stdin = child.stdin().take();
+        let body = self.body(env)?;
+        let (written, output) = tokio::join!(feed_stdin(stdin, Some(&body)), child.wait_with_output());
+        written?;
+        let output = output?;
+        if output.status.success() {
+            Ok(path)
+        } else {
+            Err(anyhow!(
+                "Failed to upload script to {remote}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+
+    /// Write out a bash script to /tmp for execution, named uniquely per run
+    fn write_script(&self, env: &[(String, String)]) -> Result<PathBuf> {
+        let path = self.script_path(&unique_id());
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.script_mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        let mut file = options
+            .open(&path)
+            .map_err(|e| anyhow!("Failed to write script {}: {e}", path.display()))?;
+
+        // The mode passed to open only applies when the file is created
+        #[cfg(unix)]
+        if let Some(mode) = self.script_mode {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+        }
+
+        file.write_all(self.body(env)?.as_bytes())?;
+        Ok(path)
+    }
+
+    /// Shared by local and remote script files
+    fn script_file_name(&self, id: &str) -> String {
+        format!(
+            "checkmate_{id}_{}.{}",
+            self.name.replace('/', "_"),
+            self.shell.extension()
+        )
+    }
+
+    fn script_path(&self, id: &str) -> PathBuf {
+        std::env::temp_dir().join(self.script_file_name(id))
+    }
+
+    /// The script's own text, read from `script_file` when it's set
+    pub fn text(&self) -> Result<String> {
+        match &self.script_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| anyhow!("Failed to read script file {path}: {e}")),
+            None => Ok(self.script.clone()),
+        }
+    }
+
+    /// The script's own text, with variables replaced when `interpolate` is on.
+    /// `env`, the variables set for this run, take precedence over the environment's.
+    fn source(&self, env: &[(String, String)]) -> Result<String> {
+        let text = self.text()?;
+        if !self.interpolate {
+            return Ok(text);
+        }
+        let mut variables = self.environment.variables();
+        variables.extend(env.iter().cloned());
+        let mut source = String::new();
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("${") {
+            source.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unterminated ${{ in script {}", self.name))?;
+            let name = &rest[start + 2..start + end];
+            let value = variables
+                .get(name)
+                .ok_or_else(|| anyhow!("Undefined variable {name} in script {}", self.name))?;
+            source.push_str(value);
+            rest = &rest[start + end + 1..];
+        }
+        source.push_str(rest);
+        Ok(source)
+    }
+
+    /// The script as written to disk, with any configured preamble
+    fn body(&self, env: &[(String, String)]) -> Result<String> {
+        let script = self.source(env)?;
+        let mut body = String::new();
+        // fish can't redirect its own output, its script is wrapped below
+        if self.merge_stderr && !matches!(self.shell, Shell::Fish | Shell::PowerShell) {
+            body.push_str("exec 2>&1\n");
+        }
+        // Local runs set the directory on the process instead
+        if let (Some(dir), Destination::Remote(_)) = (&self.working_dir, &self.destination) {
+            match self.shell {
+                Shell::PowerShell => body.push_str(&format!(
+                    "Set-Location -LiteralPath '{}' -ErrorAction Stop\n",
+                    dir.replace('\'', "''")
+                )),
+                _ => body.push_str(&format!("cd '{}' || exit 1\n", dir.replace('\'', r"'\''"))),
+            }
+        }
+        if let Shell::PowerShell = self.shell {
+            return Ok(self.powershell_body(body, &script));
+        }
+        if let Some(trap) = &self.exit_trap {
+            match self.shell {
+                // fish has no trap builtin, run the handler on its exit event instead
+                Shell::Fish => body.push_str(&format!(
+                    "function __checkmate_exit --on-event fish_exit\n{trap}\nend\n"
+                )),
+                _ => body.push_str(&format!("trap '{}' EXIT\n", trap.replace('\'', r"'\''"))),
+            }
+        }
+        match self.shell {
+            Shell::Fish if self.merge_stderr => {
+                body.push_str(&format!("begin\n{script}\nend 2>&1"))
+            }
+            _ => body.push_str(&script),
+        }
+        if self.waits_in_shell() {
+            // Keep the script's own exit status rather than wait's
+            match self.shell {
+                Shell::Fish => body.push_str("\nset __checkmate_status $status\nwait\nexit $__checkmate_status\n"),
+                _ => body.push_str("\n__checkmate_status=$?\nwait\nexit $__checkmate_status\n"),
+            }
+        }
+        Ok(body)
+    }
+
+    /// Whether the shell has to wait for the script's background jobs itself,
+    /// local runs wait for their process group instead
+    fn waits_in_shell(&self) -> bool {
+        self.wait_for_children && (cfg!(not(unix)) || matches!(self.destination, Destination::Remote(_)))
+    }
+
+    /// PowerShell has no trap on exit, the script runs in a try with the handler in its finally
+    fn powershell_body(&self, mut body: String, script: &str) -> String {
+        if self.exit_trap.is_some() {
+            body.push_str("try {\n");
+        }
+        if self.merge_stderr {
+            body.push_str(&format!("& {{\n{script}\n}} 2>&1"));
+        } else {
+            body.push_str(script);
+        }
+        if self.waits_in_shell() {
+            // Keep the script's own exit code rather than the jobs'
+            body.push_str(
+                "\n$__checkmate_status = $LASTEXITCODE\nGet-Job | Wait-Job | Out-Null\nexit $__checkmate_status\n",
+            );
+        }
+        if let Some(trap) = &self.exit_trap {
+            body.push_str(&format!("\n}} finally {{\n{trap}\n}}\n"));
+        }
+        body
+    }
+}
+
+impl Environment {
+    /// Variables the script is interpolated with. Local scripts inherit checkmate's own
+    /// whichever environment they ask for, so both give the same.
+    fn variables(&self) -> HashMap<String, String> {
+        match self {
+            Environment::None | Environment::Current => std::env::vars().collect(),
+        }
+    }
+
+    fn with_shell(&self, shell: &Shell) -> Result<String> {
+        match self {
+            Environment::None => Ok(shell.path()?),
+            _ => Ok(shell.path()?),
+        }
+    }
+}
+
+impl Shell {
+    fn path(&self) -> Result<String> {
+        match self {
+            Shell::Bash => Ok("bash".into()),
+            Shell::Zsh => Ok("zsh".into()),
+            Shell::Sh => Ok("sh".into()),
+            Shell::Fish => Ok("fish".into()),
+            Shell::PowerShell if cfg!(windows) => Ok("powershell".into()),
+            Shell::PowerShell => Ok("pwsh".into()),
+            Shell::Custom(x) => Ok(x.clone()),
+        }
+    }
+
+    /// Extension of script files, PowerShell refuses to run a file without its own
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Sh | Shell::Custom(_) => "sh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "ps1",
+        }
+    }
+
+    /// Arguments before the path of a script file
+    fn file_args(&self) -> &'static [&'static str] {
+        match self {
+            // Unsigned scripts are blocked by the default execution policy on Windows
+            Shell::PowerShell => &["-NoProfile", "-NonInteractive", "-ExecutionPolicy", "Bypass", "-File"],
+            _ => &[],
+        }
+    }
+
+    /// Arguments to read the script from stdin
+    fn stdin_args(&self) -> &'static [&'static str] {
+        match self {
+            Shell::PowerShell => &["-NoProfile", "-NonInteractive", "-Command", "-"],
+            _ => &[],
+        }
+    }
+}
+
+impl std::fmt::Display for Task {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Task::Script(s) => write!(f, "{:?}", s.destination),
-            _ => write!(f, "Serial")
+            Task::Script(s) => write!(f, "{}", s.destination),
+            _ => write!(f, "{}", self.type_name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listed_exit_codes_count_as_success() {
+        let script = Script {
+            success_codes: vec![0, 1],
+            ..Script::new("no match", "exit 1")
+        };
+        let result = script.run();
+        assert_eq!(result.as_ref().unwrap().status.code(), Some(1));
+        assert_eq!(script.status(&result), Status::Complete);
+        assert_eq!(script.status(&result).to_string(), "Complete");
+    }
+
+    #[test]
+    fn serial_task_stops_at_the_first_failure() {
+        let marker = std::env::temp_dir().join(format!("checkmate-serial-{}", std::process::id()));
+        let task = Task::Serial(vec![
+            Script::new("one", "true"),
+            Script::new("two", "exit 1"),
+            Script::new("three", format!("touch {}", marker.display())),
+        ]);
+        let progress: Vec<Progress> = task.scripts().iter().map(|_| Progress::default()).collect();
+        let result = task.run(&Control::default(), &progress).unwrap();
+
+        let outputs = result.outputs();
+        assert_eq!(task.scripts()[0].status(&outputs[0]), Status::Complete);
+        assert_eq!(task.scripts()[1].status(&outputs[1]), Status::Failed(1));
+        assert!(outputs[2].as_ref().unwrap_err().is::<Skipped>());
+        assert_eq!(progress[2].attempts.load(Ordering::SeqCst), 0);
+        assert!(!marker.exists());
+        assert_eq!(task.status(&result), Status::Failed(1));
+    }
+
+    #[test]
+    fn timeout_kills_the_script() {
+        let script = Script::builder("hang", "sleep 10").timeout(1).build();
+        let start = Instant::now();
+        let result = script.run();
+        let elapsed = start.elapsed();
+
+        assert_eq!(script.status(&result), Status::TimedOut);
+        assert_eq!(result.unwrap_err().to_string(), "Timed out after 1s");
+        assert!(elapsed >= Duration::from_secs(1) && elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    /// A directory of its own under the system temp directory, removed first if left over
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("checkmate-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn log_files_match_the_output() {
+        let dir = temp_dir("logs");
+        let mut job = Job::new("test", vec![Task::local("say/hi", "echo out; echo err >&2; echo more")]);
+        job.log_dir = Some(dir.display().to_string());
+        let runner = job.run();
+        runner.wait_all().unwrap();
+
+        let results = runner.results();
+        let output = results[0].outputs()[0].as_ref().unwrap();
+        assert_eq!(std::fs::read(dir.join("say_hi.stdout.log")).unwrap(), output.stdout);
+        assert_eq!(std::fs::read(dir.join("say_hi.stderr.log")).unwrap(), output.stderr);
+        assert_eq!(output.stdout, b"out\nmore\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn artifacts_download_over_the_ssh_session() {
+        let remote: Remote = serde_json::from_str(r#"{"host": "build", "user": "ci", "port": 2222}"#).unwrap();
+        let script = Script {
+            download: vec![Artifact {
+                remote: "/tmp/report.xml".into(),
+                local: "report.xml".into(),
+            }],
+            ..Script::builder("build", "make").destination(Destination::Remote(remote)).build()
+        };
+        let plan = script.plan();
+        assert_eq!(plan.last().unwrap(), "ssh -p 2222 ci@build cat /tmp/report.xml > report.xml");
+        assert!(plan.iter().all(|step| !step.starts_with("scp")), "{plan:?}");
+    }
+
+    #[test]
+    fn stderr_output_fails_a_zero_exit() {
+        let script = Script {
+            fail_on_stderr: true,
+            ..Script::new("warn", "echo warning >&2")
+        };
+        let result = script.run();
+        assert!(result.as_ref().unwrap().status.success());
+        assert_eq!(script.status(&result), Status::Stderr);
+        assert_eq!(script.status(&result).to_string(), "Failed (stderr)");
+
+        let quiet = Script {
+            fail_on_stderr: true,
+            ..Script::new("quiet", "echo fine")
+        };
+        assert_eq!(quiet.status(&quiet.run()), Status::Complete);
+    }
+
+    #[test]
+    fn cancel_wakes_a_task_waiting_for_a_slot() {
+        let runner = Job {
+            max_parallel: Some(1),
+            ..Job::new("test", vec![Task::local("hang", "sleep 10"), Task::local("queued", "true")])
+        }
+        .run();
+        while runner.threads[0].started().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let start = Instant::now();
+        runner.threads[1].cancel.cancel();
+        while !runner.threads[1].is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(2), "still waiting for a slot");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(runner.threads[1].status(), Status::Cancelled);
+        assert!(runner.threads[1].started().is_none());
+        runner.cancel_all();
+    }
+
+    #[test]
+    fn cancel_wakes_a_script_waiting_for_its_mutex_or_a_resume() {
+        let control = Control::default();
+        let script = Script {
+            mutex: Some("db".into()),
+            ..Script::new("migrate", "true")
+        };
+        let cancelled = |control: &Control| {
+            let waiting = Control {
+                cancel: CancelToken::default(),
+                ..control.clone()
+            };
+            let cancel = waiting.cancel.clone();
+            let start = Instant::now();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(200));
+                cancel.cancel();
+            });
+            let result = waiting.run(&script, &Progress::default());
+            assert!(start.elapsed() < Duration::from_secs(2));
+            result.unwrap_err().is::<Cancelled>()
+        };
+
+        let held = control.lock("db").unwrap();
+        assert!(cancelled(&control));
+        drop(held);
+
+        control.toggle_pause();
+        assert!(cancelled(&control));
+        control.toggle_pause();
+        assert!(control.run(&script, &Progress::default()).is_ok());
+    }
+
+    #[test]
+    fn same_name_scripts_keep_separate_temp_files() {
+        let task = Task::Parallel(vec![
+            Script::new("default", "sleep 0.2; echo one; echo $0 >&2"),
+            Script::new("default", "sleep 0.2; echo two; echo $0 >&2"),
+        ]);
+        let progress: Vec<Progress> = task.scripts().iter().map(|_| Progress::default()).collect();
+        let result = task.run(&Control::default(), &progress).unwrap();
+
+        let outputs: Vec<&Output> = result.outputs().iter().map(|o| o.as_ref().unwrap()).collect();
+        assert_eq!(outputs[0].stdout, b"one\n");
+        assert_eq!(outputs[1].stdout, b"two\n");
+        let paths: Vec<String> = outputs
+            .iter()
+            .map(|o| String::from_utf8_lossy(&o.stderr).trim().to_string())
+            .collect();
+        assert_ne!(paths[0], paths[1]);
+        for path in paths {
+            assert!(path.ends_with("_default.sh"), "{path}");
+            assert!(!Path::new(&path).exists(), "{path} was left behind");
+        }
+    }
+
+    #[test]
+    fn temp_scripts_are_removed_however_the_run_ends() {
+        let path_of = |output: &Output| String::from_utf8_lossy(&output.stderr).trim().to_string();
+
+        for body in ["echo $0 >&2", "echo $0 >&2; exit 2"] {
+            let script = Script::new("cleanup", body);
+            let path = path_of(&script.run().unwrap());
+            assert!(path.contains("checkmate_"), "{path}");
+            assert!(!Path::new(&path).exists(), "{path} was left behind");
+        }
+
+        let progress = Progress::default();
+        let script = Script::builder("cleanup", "echo $0 >&2; sleep 10").timeout(1).build();
+        assert!(script.run_counted(&progress, &Control::default()).is_err());
+        let path = String::from_utf8_lossy(&progress.stderr.lock().unwrap()).trim().to_string();
+        assert!(path.contains("checkmate_"), "{path}");
+        assert!(!Path::new(&path).exists(), "{path} was left behind");
+    }
+
+    #[test]
+    fn flaky_script_succeeds_on_a_retry() {
+        let counter = temp_dir("retries").join("count");
+        std::fs::create_dir_all(counter.parent().unwrap()).unwrap();
+        let body = format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); n=$((n + 1)); echo $n > {0}; echo attempt $n; [ $n -ge 3 ]",
+            counter.display()
+        );
+        let script = Script {
+            retries: 3,
+            ..Script::new("flaky", body)
+        };
+        let progress = Progress::default();
+        let result = script.run_counted(&progress, &Control::default());
+        std::fs::remove_dir_all(counter.parent().unwrap()).unwrap();
+
+        assert_eq!(script.status(&result), Status::Complete);
+        assert_eq!(result.unwrap().stdout, b"attempt 3\n");
+        assert_eq!(progress.attempts.load(Ordering::SeqCst), 3);
+
+        let hopeless = Script {
+            retries: 2,
+            ..Script::new("hopeless", "exit 1")
+        };
+        let progress = Progress::default();
+        let result = hopeless.run_counted(&progress, &Control::default());
+        assert_eq!(hopeless.status(&result), Status::Failed(1));
+        assert_eq!(progress.attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn cipher_and_compression_reach_ssh() {
+        let remote: Remote = serde_json::from_str(
+            r#"{"host": "127.0.0.1", "port": 1, "compression": false, "cipher": "aes128-ctr", "connect_timeout": 2}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            remote.ssh_args(),
+            ["-p 1", "-o Compression=no", "-c aes128-ctr", "-o ConnectTimeout=2"]
+        );
+
+        let config = Remote::cipher_config("aes128-ctr").unwrap();
+        let contents = std::fs::read_to_string(&config).unwrap();
+        std::fs::remove_file(&config).unwrap();
+        assert_eq!(
+            contents,
+            "Ciphers aes128-ctr\nInclude ~/.ssh/config\nInclude /etc/ssh/ssh_config\n"
+        );
+
+        // Refused straight away, the config is gone once connecting gave up
+        let configs = || {
+            let prefix = format!("checkmate_ssh_{}_", std::process::id());
+            std::fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix))
+                .count()
+        };
+        let connected = Runtime::new().unwrap().block_on(remote.connect(&KnownHostsPolicy::default()));
+        assert!(connected.unwrap_err().is::<ConnectionFailed>());
+        assert_eq!(configs(), 0);
+    }
+
+    #[test]
+    fn results_hold_the_finished_tasks_in_job_order() {
+        let runner = Job::new("test", vec![
+            Task::local("slow", "sleep 10"),
+            Task::local("a", "echo a"),
+            Task::Serial(vec![Script::new("b", "echo b"), Script::new("c", "echo c >&2")]),
+        ])
+        .run();
+        while !(runner.threads[1].is_finished() && runner.threads[2].is_finished()) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let results = runner.results();
+        assert_eq!(results.len(), 2);
+        let TaskResult::Script(Ok(a)) = &results[0] else {
+            panic!("{:?}", results[0]);
+        };
+        assert_eq!(a.stdout, b"a\n");
+        let TaskResult::Serial(steps) = &results[1] else {
+            panic!("{:?}", results[1]);
+        };
+        assert_eq!(steps[0].as_ref().unwrap().stdout, b"b\n");
+        assert_eq!(steps[1].as_ref().unwrap().stderr, b"c\n");
+        assert!(steps.iter().all(|step| step.as_ref().unwrap().status.success()));
+
+        runner.cancel_all();
+        runner.wait_all().unwrap_err();
+        assert_eq!(runner.results().len(), 3);
+    }
+
+    #[test]
+    fn wait_all_reports_failures_and_gives_up_at_its_timeout() {
+        let runner = Job::new("test", vec![Task::local("a", "true"), Task::local("b", "echo b")]).run();
+        runner.wait_all().unwrap();
+        assert_eq!(runner.finished(), 2);
+
+        let runner = Job::new("test", vec![Task::local("a", "true"), Task::local("b", "exit 4")]).run();
+        assert_eq!(runner.wait_all().unwrap_err().to_string(), "Tasks failed: b [Failed (exit 4)]");
+
+        let runner = Job::new("test", vec![Task::local("a", "true"), Task::local("hang", "sleep 10")]).run();
+        let start = Instant::now();
+        let error = runner.wait_all_timeout(Duration::from_millis(300)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(error.to_string(), "1 of 2 tasks still running after 300ms");
+        runner.cancel_all();
+    }
+
+    #[test]
+    fn status_lines_name_the_verdict_and_detail() {
+        // The duration varies, so compare up to it and check it's there
+        let verdict = |line: String| {
+            let (verdict, duration) = line.rsplit_once([' ', '(']).unwrap();
+            let seconds = duration.strip_suffix("s)").map(str::parse::<f64>);
+            assert!(matches!(seconds, Some(Ok(_))), "{line}");
+            verdict.trim_end().to_string()
+        };
+
+        let runner = Job::new("test", vec![
+            Task::local("deploy", "true"),
+            Task::local("build", "exit 2"),
+            Task::local("killed", "kill -9 $$"),
+            Task::Script(Script {
+                depends_on: vec!["build".into()],
+                ..Script::new("publish", "true")
+            }),
+            Task::Script(Script {
+                error_markers: vec!["FATAL".into()],
+                markers_fail: true,
+                ..Script::new("noisy", "echo FATAL; echo FATAL")
+            }),
+        ])
+        .run();
+        runner.wait_all().unwrap_err();
+        let lines: Vec<String> = runner.threads.iter().map(|t| verdict(t.status_line())).collect();
+        assert_eq!(
+            lines,
+            [
+                "deploy: OK",
+                "build: FAILED (exit 2,",
+                "killed: FAILED (signal 9,",
+                "publish: SKIPPED",
+                "noisy: FAILED (2 errors in output,",
+            ]
+        );
+
+        let runner = Job {
+            max_parallel: Some(1),
+            ..Job::new("test", vec![Task::local("hang", "sleep 10"), Task::local("next", "true")])
+        }
+        .run();
+        while runner.threads[0].started().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(verdict(runner.threads[0].status_line()), "hang: RUNNING");
+        assert_eq!(runner.threads[1].status_line(), "next: QUEUED (0.0s)");
+        runner.cancel_all();
+        runner.wait_all().unwrap_err();
+        assert_eq!(verdict(runner.threads[0].status_line()), "hang: CANCELLED");
+    }
+
+    #[test]
+    fn warmup_runs_are_discarded() {
+        let counter = temp_dir("warmup").join("count");
+        std::fs::create_dir_all(counter.parent().unwrap()).unwrap();
+        // Only the last run succeeds, so a reported warmup would show as a failure
+        let body = format!(
+            "n=$(cat {0} 2>/dev/null || echo 0); n=$((n + 1)); echo $n > {0}; echo run $n; [ $n -eq 3 ]",
+            counter.display()
+        );
+        let script = Script {
+            warmup: 2,
+            ..Script::new("bench", body)
+        };
+        let progress = Progress::default();
+        let result = script.run_counted(&progress, &Control::default());
+        let runs = std::fs::read_to_string(&counter).unwrap();
+        std::fs::remove_dir_all(counter.parent().unwrap()).unwrap();
+
+        assert_eq!(runs.trim(), "3");
+        assert_eq!(script.status(&result), Status::Complete);
+        assert_eq!(result.unwrap().stdout, b"run 3\n");
+        assert_eq!(progress.warmups.load(Ordering::SeqCst), 2);
+        assert_eq!(progress.attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn remotes_inherit_their_host_defaults_unless_set() {
+        let remote = |json: &str| Destination::Remote(serde_json::from_str(json).unwrap());
+        let on = |name: &str, destination: Destination| {
+            Task::Script(Script::builder(name, "true").destination(destination).build())
+        };
+        let mut job = Job {
+            destinations: vec![DestinationDefaults {
+                host: "web".into(),
+                port: Some(2222),
+                user: Some("deploy".into()),
+                ..DestinationDefaults::default()
+            }],
+            ..Job::new("test", vec![
+                on("inherits", remote(r#""web""#)),
+                on("overrides", remote(r#"{"host": "web", "port": 22}"#)),
+                on("other host", remote(r#""db""#)),
+                on("local", Destination::Local),
+            ])
+        };
+        job.apply_destination_defaults();
+
+        let settings: Vec<_> = job
+            .tasks
+            .iter()
+            .map(|t| match &t.scripts()[0].destination {
+                Destination::Remote(r) => Some((r.port, r.user.as_deref())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            settings,
+            [
+                Some((Some(2222), Some("deploy"))),
+                Some((Some(22), Some("deploy"))),
+                Some((None, None)),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_plan_uploads_runs_and_removes_a_remote_script() {
+        let remote: Remote = "ci@build".into();
+        let script = Script {
+            script_mode: Some(0o700),
+            ..Script::builder("deploy", "make deploy").destination(Destination::Remote(remote)).build()
+        };
+        let file = "/tmp/checkmate_<id>_deploy.sh";
+        assert_eq!(
+            script.plan(),
+            [
+                format!("ssh ci@build cat > {file} < <script>"),
+                format!("ssh ci@build chmod 700 {file}"),
+                format!("ssh ci@build bash {file} (clean environment)"),
+                format!("ssh ci@build rm -f {file}"),
+            ]
+        );
+
+        let local = Script::new("local", "true");
+        let plan = local.plan();
+        assert_eq!(plan.len(), 2);
+        assert!(plan[0].starts_with("write ") && plan[0].ends_with("_local.sh"), "{plan:?}");
+        assert!(plan[1].starts_with("bash ") && plan[1].ends_with("_local.sh (clean environment)"), "{plan:?}");
+    }
+
+    #[test]
+    fn waits_for_background_commands_and_reaps_them() {
+        let script = Script {
+            wait_for_children: true,
+            timeout: Some(5),
+            ..Script::new(
+                "background",
+                "(sleep 0.3; echo late) &\nsh -c 'sleep 0.2; echo orphan' &\necho early",
+            )
+        };
+        let start = Instant::now();
+        let result = script.run();
+        let elapsed = start.elapsed();
+
+        assert_eq!(script.status(&result), Status::Complete);
+        assert_eq!(result.unwrap().stdout, b"early\norphan\nlate\n");
+        assert!(elapsed >= Duration::from_millis(300) && elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    #[test]
+    fn timed_out_background_commands_are_killed() {
+        let marker = temp_dir("orphan").with_extension("done");
+        let script = Script {
+            wait_for_children: true,
+            timeout: Some(1),
+            ..Script::new("daemon", format!("sh -c 'sleep 2; touch {}' &", marker.display()))
+        };
+        assert_eq!(script.status(&script.run()), Status::TimedOut);
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists(), "the background command outlived the timeout");
+    }
+
+    #[test]
+    fn summary_totals_bytes_and_time_across_tasks() {
+        let runner = Job::new("test", vec![
+            Task::local("a", "sleep 0.3; echo hello"),
+            Task::local("b", "sleep 0.3; echo oops >&2"),
+            Task::Serial(vec![Script::new("c", "printf abc"), Script::new("d", "exit 1")]),
+        ])
+        .run();
+        runner.wait_all().unwrap_err();
+
+        let summary = runner.summary();
+        assert_eq!(summary.bytes, "hello\n".len() + "oops\n".len() + "abc".len());
+        let task_time: Duration = runner.threads.iter().map(|t| t.duration()).sum();
+        assert_eq!(summary.task_time, task_time);
+        // The two sleeps overlap
+        assert!(summary.wall >= Duration::from_millis(300), "{summary:?}");
+        assert!(summary.wall < task_time, "{summary:?}");
+        assert!(summary.parallelism() > 1.0, "{summary:?}");
+        assert_eq!(Summary::default().parallelism(), 0.0);
+
+        let shown = summary.to_string();
+        assert!(shown.starts_with("14 bytes captured, "), "{shown}");
+        assert!(shown.ends_with("x parallel"), "{shown}");
+    }
+
+    #[test]
+    fn missing_ssh_is_named() {
+        let remote: Remote = "build".into();
+        let not_found = || openssh::Error::Connect(std::io::ErrorKind::NotFound.into());
+        let error = |error, path: &str| remote.connect_error(error, Some(path.as_ref())).to_string();
+
+        assert_eq!(error(not_found(), "/nonexistent"), "ssh not found; install openssh-client");
+        let path = std::env::var("PATH").unwrap();
+        assert_eq!(
+            error(not_found(), &path),
+            "Failed to connect to build: failed to connect to the remote host"
+        );
+        let refused = openssh::Error::Connect(std::io::ErrorKind::ConnectionRefused.into());
+        assert!(error(refused, "/nonexistent").starts_with("Failed to connect to build"));
+    }
+
+    #[test]
+    fn unreachable_host_fails_within_its_connect_timeout() {
+        // TEST-NET-1, reserved and never routed
+        let remote: Remote = serde_json::from_str(r#"{"host": "192.0.2.1", "connect_timeout": 1}"#).unwrap();
+        let runner = Job::new("test", vec![Task::Script(
+            Script::builder("unreachable", "true").destination(Destination::Remote(remote)).build(),
+        )])
+        .run();
+        runner.wait_all_timeout(Duration::from_secs(5)).unwrap_err();
+
+        assert_eq!(runner.threads[0].status(), Status::ConnectionFailed);
+        let TaskResult::Script(Err(error)) = &runner.results()[0] else {
+            panic!("{:?}", runner.results());
+        };
+        // Timed out, or refused straight away where there is no route at all
+        let error = error.to_string();
+        assert!(
+            error == "Connection to 192.0.2.1 timed out after 1s"
+                || error.starts_with("Failed to connect to 192.0.2.1"),
+            "{error}"
+        );
+    }
+
+    fn depending(name: &str, depends_on: &[&str]) -> Task {
+        Task::Script(Script {
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            ..Script::new(name, "true")
+        })
+    }
+
+    #[test]
+    fn dependency_order_puts_dependencies_first() {
+        let job = Job::new("test", vec![depending("c", &["b"]), depending("b", &["a"]), depending("a", &[])]);
+        assert_eq!(job.dependency_order().unwrap(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn dependency_order_rejects_cycles() {
+        let job = Job::new("test", vec![depending("a", &["b"]), depending("b", &["a"]), depending("c", &[])]);
+        let error = job.dependency_order().unwrap_err().to_string();
+        assert_eq!(error, "Dependency cycle between a, b");
+    }
+
+    #[test]
+    fn dependency_order_rejects_unknown_names() {
+        let job = Job::new("test", vec![depending("a", &["missing"])]);
+        let error = job.dependency_order().unwrap_err().to_string();
+        assert_eq!(error, "a depends on unknown script missing");
+    }
+
+    #[test]
+    fn diamond_runs_each_task_after_its_dependencies() {
+        let sleeping = |name: &str, depends_on: &[&str]| {
+            Task::Script(Script {
+                depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+                ..Script::new(name, "sleep 0.2")
+            })
+        };
+        let runner = Job::new("test", vec![
+            sleeping("deploy", &["test a", "test b"]),
+            sleeping("test a", &["build"]),
+            sleeping("test b", &["build"]),
+            sleeping("build", &[]),
+        ])
+        .run();
+        runner.wait_all().unwrap();
+
+        let [deploy, test_a, test_b, build] = [0, 1, 2, 3].map(|i| {
+            let t = &runner.threads[i];
+            let started = t.started().unwrap();
+            (started, started + t.duration())
+        });
+        assert!(test_a.0 >= build.1 && test_b.0 >= build.1);
+        assert!(deploy.0 >= test_a.1 && deploy.0 >= test_b.1);
+        // The two tests don't depend on each other, so they overlap
+        assert!(test_a.0 < test_b.1 && test_b.0 < test_a.1);
+    }
+
+    #[test]
+    fn max_parallel_keeps_tasks_from_overlapping() {
+        let runner = Job {
+            max_parallel: Some(1),
+            ..Job::new("test", (0..3).map(|i| Task::local(format!("task {i}"), "sleep 0.2")).collect())
+        }
+        .run();
+        // Only one can have left the queue so far
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(runner.threads.iter().filter(|t| t.is_queued()).count(), 2);
+        assert_eq!(
+            runner.threads.iter().filter(|t| t.status() == Status::Queued).count(),
+            2
+        );
+        runner.wait_all().unwrap();
+
+        let mut spans: Vec<(Instant, Instant)> = runner
+            .threads
+            .iter()
+            .map(|t| (t.started().unwrap(), t.started().unwrap() + t.duration()))
+            .collect();
+        spans.sort();
+        for pair in spans.windows(2) {
+            assert!(pair[1].0 >= pair[0].1, "{pair:?}");
+        }
+    }
+
+    #[test]
+    fn scripts_run_in_their_working_dir() {
+        let dir = temp_dir("working-dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let script = Script {
+            working_dir: Some(dir.display().to_string()),
+            ..Script::new("pwd", "pwd; touch relative")
+        };
+        let result = script.run();
+        let created = dir.join("relative").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&result.unwrap().stdout).trim(), dir.display().to_string());
+        assert!(created);
+
+        let missing = Script {
+            working_dir: Some("/nonexistent/checkmate".into()),
+            ..Script::new("pwd", "pwd")
+        };
+        assert_eq!(
+            missing.run().unwrap_err().to_string(),
+            "Working directory /nonexistent/checkmate does not exist"
+        );
+    }
+
+    #[test]
+    fn duration_ticks_while_running_and_freezes_once_finished() {
+        let runner = Job::new("test", vec![Task::local("nap", "sleep 0.5")]).run();
+        let nap = &runner.threads[0];
+        while nap.started().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let early = nap.duration();
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(nap.duration() > early, "{early:?}");
+
+        runner.wait_all().unwrap();
+        let total = nap.duration();
+        assert!(total >= Duration::from_millis(500) && total < Duration::from_millis(900), "{total:?}");
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(nap.duration(), total);
+    }
+
+    #[test]
+    fn interpolate_fills_in_the_environment_and_the_run_variables() {
+        std::env::set_var("CHECKMATE_TEST_GREETING", "hello");
+        let script = Script {
+            interpolate: true,
+            ..Script::new("greet", "echo ${CHECKMATE_TEST_GREETING} ${CHECKMATE_JOB} $HOME")
+        };
+        let env = [("CHECKMATE_JOB".to_string(), "nightly".to_string())];
+        assert_eq!(script.source(&env).unwrap(), "echo hello nightly $HOME");
+
+        // The run's variables win over checkmate's own
+        let progress = Progress {
+            env: vec![("CHECKMATE_TEST_GREETING".into(), "hi".into()), env[0].clone()],
+            ..Progress::default()
+        };
+        let output = script.run_counted(&progress, &Control::default()).unwrap();
+        assert!(output.stdout.starts_with(b"hi nightly "), "{output:?}");
+    }
+
+    #[test]
+    fn interpolate_rejects_unknown_and_unterminated_variables() {
+        let unknown = Script {
+            interpolate: true,
+            ..Script::new("unknown", "echo ${CHECKMATE_TEST_UNSET}")
+        };
+        assert_eq!(
+            unknown.source(&[]).unwrap_err().to_string(),
+            "Undefined variable CHECKMATE_TEST_UNSET in script unknown"
+        );
+        let unterminated = Script {
+            interpolate: true,
+            ..Script::new("unterminated", "echo ${HOME")
+        };
+        assert_eq!(
+            unterminated.source(&[]).unwrap_err().to_string(),
+            "Unterminated ${ in script unterminated"
+        );
+
+        let plain = Script::new("plain", "echo ${CHECKMATE_TEST_UNSET}");
+        assert_eq!(plain.source(&[]).unwrap(), "echo ${CHECKMATE_TEST_UNSET}");
+    }
+
+    #[test]
+    fn rerun_job_holds_the_unsuccessful_tasks_and_merges_back() {
+        let dir = temp_dir("rerun");
+        std::fs::create_dir_all(&dir).unwrap();
+        // Fails on its first run only
+        let flaky = format!("[ -e {0} ] || {{ touch {0}; exit 1; }}", dir.join("ran").display());
+        let mut runner = Job::new("test", vec![
+            Task::local("build", "true"),
+            Task::local("flaky", flaky),
+            Task::Script(Script {
+                depends_on: vec!["build".into(), "flaky".into()],
+                ..Script::new("deploy", "true")
+            }),
+            Task::local("lint", "true"),
+        ])
+        .run();
+        runner.wait_all().unwrap_err();
+        assert_eq!(runner.failed_tasks(), ["flaky", "deploy"]);
+
+        let rerun = runner.failed_job();
+        let names: Vec<String> = rerun.tasks.iter().map(Task::name).collect();
+        assert_eq!(names, ["flaky", "deploy"]);
+        // build already succeeded, flaky is in the rerun
+        assert_eq!(rerun.tasks[1].scripts()[0].depends_on, ["flaky"]);
+
+        let rerun = rerun.run();
+        rerun.wait_all().unwrap();
+        runner.merge_rerun(rerun);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let statuses: Vec<Status> = runner.threads.iter().map(|t| t.status()).collect();
+        assert_eq!(statuses, vec![Status::Complete; 4]);
+        let names: Vec<String> = runner.threads.iter().map(|t| t.task.name()).collect();
+        assert_eq!(names, ["build", "flaky", "deploy", "lint"]);
+        assert!(runner.failed_tasks().is_empty());
+    }
+
+    #[test]
+    fn is_success_and_failed_tasks_follow_each_task_status() {
+        let runner = Job::new("test", vec![
+            Task::local("ok", "true"),
+            Task::Script(Script {
+                success_codes: vec![0, 1],
+                ..Script::new("no match", "exit 1")
+            }),
+            Task::local("bad", "exit 2"),
+            Task::Serial(vec![Script::new("first", "true"), Script::new("second", "exit 1")]),
+        ])
+        .run();
+        runner.wait_all().unwrap_err();
+
+        let successes: Vec<bool> = runner
+            .threads
+            .iter()
+            .zip(runner.results())
+            .map(|(t, result)| result.is_success(&t.task))
+            .collect();
+        assert_eq!(successes, [true, true, false, false]);
+        assert_eq!(runner.failed_tasks(), ["bad", "first => second"]);
+
+        // Unfinished tasks haven't succeeded either
+        let running = Job::new("test", vec![Task::local("hang", "sleep 10")]).run();
+        assert_eq!(running.failed_tasks(), ["hang"]);
+        running.cancel_all();
+    }
+
+    #[test]
+    fn a_shared_script_name_waits_for_every_task_with_it() {
+        let job = Job::new("test", vec![
+            Task::local("build", "sleep 0.2"),
+            Task::local("build", "sleep 0.5"),
+            depending("deploy", &["build"]),
+        ]);
+        assert_eq!(job.dependencies().unwrap()[2], [0, 1]);
+
+        let runner = job.run();
+        runner.wait_all().unwrap();
+        let deploy = runner.threads[2].started().unwrap();
+        for build in &runner.threads[..2] {
+            assert!(deploy >= build.started().unwrap() + build.duration());
+        }
+    }
+
+    #[test]
+    fn job_timeout_cancels_the_unfinished_tasks() {
+        let start = Instant::now();
+        let runner = Job {
+            timeout: Some(2),
+            ..Job::new("test", vec![Task::local("fast", "true"), Task::local("slow", "sleep 60")])
+        }
+        .run();
+        runner.wait_all().unwrap_err();
+        let elapsed = start.elapsed();
+
+        assert_eq!(runner.threads[0].status(), Status::Complete);
+        assert_eq!(runner.threads[1].status(), Status::Cancelled);
+        assert!(runner.timed_out());
+        assert!(elapsed >= Duration::from_secs(2) && elapsed < Duration::from_secs(5), "{elapsed:?}");
+    }
+
+    #[test]
+    fn scripts_sharing_a_mutex_never_overlap() {
+        let timed = |name: &str| {
+            Task::Script(Script {
+                mutex: Some("db".into()),
+                ..Script::new(name, "date +%s%N; sleep 0.3; date +%s%N")
+            })
+        };
+        let runner = Job::new("test", vec![timed("migrate"), timed("seed")]).run();
+        runner.wait_all().unwrap();
+
+        let mut spans: Vec<(u128, u128)> = runner
+            .results()
+            .iter()
+            .map(|result| {
+                let stdout = &result.outputs()[0].as_ref().unwrap().stdout;
+                let times: Vec<u128> = String::from_utf8_lossy(stdout)
+                    .lines()
+                    .map(|line| line.parse().unwrap())
+                    .collect();
+                (times[0], times[1])
+            })
+            .collect();
+        spans.sort();
+        assert!(spans[1].0 >= spans[0].1, "{spans:?}");
+    }
+
+    #[test]
+    fn nothing_starts_while_the_job_is_paused() {
+        let runner = Job::new("test", vec![
+            Task::local("first", "sleep 0.2"),
+            Task::Script(Script {
+                depends_on: vec!["first".into()],
+                ..Script::new("second", "true")
+            }),
+        ])
+        .run();
+        let start = Instant::now();
+        while runner.threads[0].attempts() != [1] {
+            assert!(start.elapsed() < Duration::from_secs(5), "first never started");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        runner.control.toggle_pause();
+
+        // The running script carries on, the next one waits for the resume
+        while !runner.threads[0].is_finished() {
+            assert!(start.elapsed() < Duration::from_secs(5), "first never finished");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(runner.threads[1].attempts(), [0]);
+        assert!(!runner.threads[1].is_finished());
+
+        runner.control.toggle_pause();
+        runner.wait_all_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(runner.threads[1].attempts(), [1]);
+    }
+
+    #[test]
+    fn stdin_delivery_writes_no_temp_file() {
+        // Counts this script's temp files while it runs
+        let probe = format!(
+            "ls {} | grep -c '_no-file.sh$' || true",
+            std::env::temp_dir().display()
+        );
+        let piped = Script {
+            delivery: ScriptDelivery::Stdin,
+            ..Script::new("no-file", probe.clone())
+        };
+        assert_eq!(piped.run().unwrap().stdout, b"0\n");
+        assert!(piped.plan().iter().all(|step| !step.starts_with("write ")), "{:?}", piped.plan());
+
+        let file = Script::new("no-file", probe);
+        assert_eq!(file.run().unwrap().stdout, b"1\n");
+    }
+
+    #[test]
+    fn merged_stderr_keeps_its_order_with_stdout() {
+        let script = Script {
+            merge_stderr: true,
+            ..Script::new("interleaved", "echo one; echo two >&2; echo three; echo four >&2")
+        };
+        let output = script.run().unwrap();
+        assert_eq!(output.stdout, b"one\ntwo\nthree\nfour\n");
+        assert!(output.stderr.is_empty());
+    }
+
+    #[test]
+    fn a_missing_shell_is_reported_not_spawned() {
+        let script = Script::builder("bogus", "true")
+            .shell(Shell::Custom("checkmate-no-such-shell".into()))
+            .build();
+        let result = script.run();
+        assert_eq!(script.status(&result), Status::ShellNotFound("checkmate-no-such-shell".into()));
+        assert_eq!(result.unwrap_err().to_string(), "Shell not found: checkmate-no-such-shell");
+    }
+
+    #[test]
+    fn output_past_the_cap_keeps_only_its_tail() {
+        let script = Script {
+            max_output_bytes: Some(64 * 1024),
+            ..Script::new("flood", "head -c 1048576 /dev/zero | tr '\\0' x")
+        };
+        let stdout = script.run().unwrap().stdout;
+
+        let note = format!("[output truncated, {} bytes dropped]\n", 1024 * 1024 - 64 * 1024);
+        assert_eq!(stdout.len(), note.len() + 64 * 1024);
+        assert!(stdout.starts_with(note.as_bytes()));
+        assert!(stdout[note.len()..].iter().all(|&b| b == b'x'));
+    }
+
+    #[test]
+    fn stdin_round_trips_through_cat() {
+        let input = "line one\nline two\n\tand a tab\n";
+        let script = Script {
+            stdin: Some(input.into()),
+            ..Script::new("echo back", "cat")
+        };
+        let output = script.run().unwrap();
+        assert_eq!(output.stdout, input.as_bytes());
+        assert!(script.resolved_command().ends_with(" < <stdin>"), "{}", script.resolved_command());
+    }
+
+    #[test]
+    fn powershell_runs_the_trap_in_a_finally() {
+        let script = Script {
+            exit_trap: Some("Write-Host done".into()),
+            merge_stderr: true,
+            ..Script::builder("ps", "Write-Output hi").shell(Shell::PowerShell).build()
+        };
+        assert_eq!(
+            script.body(&[]).unwrap(),
+            "try {\n& {\nWrite-Output hi\n} 2>&1\n} finally {\nWrite-Host done\n}\n"
+        );
+
+        let program = if cfg!(windows) { "powershell" } else { "pwsh" };
+        assert_eq!(script.program(&[]).unwrap(), (program.to_string(), vec![]));
+        let command = script.resolved_command();
+        assert!(
+            command.starts_with(&format!("{program} -NoProfile -NonInteractive -ExecutionPolicy Bypass -File ")),
+            "{command}"
+        );
+        assert!(command.ends_with("_ps.ps1"), "{command}");
+    }
+
+    #[test]
+    fn script_files_take_their_shell_extension() {
+        let shells = [
+            Shell::Bash,
+            Shell::Zsh,
+            Shell::Sh,
+            Shell::Fish,
+            Shell::PowerShell,
+            Shell::Custom("dash".into()),
+        ];
+        let extensions = shells.clone().map(|shell| shell.extension());
+        assert_eq!(extensions, ["sh", "sh", "sh", "fish", "ps1", "sh"]);
+        for (shell, extension) in shells.into_iter().zip(extensions) {
+            let script = Script::builder("step", "true").shell(shell).build();
+            assert_eq!(script.script_file_name("1"), format!("checkmate_1_step.{extension}"));
+        }
+    }
+
+    #[test]
+    fn run_job_gives_every_result_in_job_order() {
+        let job = Job::new("test", vec![Task::local("ok", "echo ok"), Task::local("bad", "exit 3")]);
+        let results = run_job(job).unwrap();
+        assert_eq!(results.len(), 2);
+        let codes: Vec<Option<i32>> = results
+            .iter()
+            .map(|r| r.outputs()[0].as_ref().unwrap().status.code())
+            .collect();
+        assert_eq!(codes, [Some(0), Some(3)]);
+        assert_eq!(results[0].outputs()[0].as_ref().unwrap().stdout, b"ok\n");
+
+        let invalid = run_job(Job::new("test", vec![depending("a", &["missing"])]));
+        assert_eq!(invalid.unwrap_err().to_string(), "a depends on unknown script missing");
+    }
+
+    #[test]
+    fn local_temp_script_gets_its_script_mode() {
+        let script = Script {
+            script_mode: Some(0o750),
+            ..Script::new("mode", "stat -c %a \"$0\"")
+        };
+        assert_eq!(script.run().unwrap().stdout, b"750\n");
+    }
+
+    #[test]
+    fn exit_trap_is_prepended_for_each_shell() {
+        let body = |shell: Shell| {
+            Script {
+                exit_trap: Some("echo 'bye'".into()),
+                ..Script::builder("trapped", "echo hi").shell(shell).build()
+            }
+            .body(&[])
+            .unwrap()
+        };
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Sh, Shell::Custom("dash".into())] {
+            assert_eq!(body(shell), "trap 'echo '\\''bye'\\''' EXIT\necho hi");
+        }
+        assert_eq!(
+            body(Shell::Fish),
+            "function __checkmate_exit --on-event fish_exit\necho 'bye'\nend\necho hi"
+        );
+
+        let ran = Script {
+            exit_trap: Some("echo trapped".into()),
+            ..Script::new("trapped", "echo hi; exit 3")
+        }
+        .run()
+        .unwrap();
+        assert_eq!(ran.stdout, b"hi\ntrapped\n");
+        assert_eq!(ran.status.code(), Some(3));
+    }
+
+    #[test]
+    fn on_complete_hook_gets_the_failure_count() {
+        let mut job = Job::new("test", vec![Task::local("ok", "true"), Task::local("bad", "exit 1")]);
+        job.on_complete = Some(Script::new(
+            "report",
+            "echo \"$CHECKMATE_JOB $CHECKMATE_TASK_COUNT $CHECKMATE_COMPLETE_COUNT $CHECKMATE_FAILED_COUNT\"",
+        ));
+        let runner = job.run();
+        assert!(runner.wait_all().is_err());
+
+        let output = runner.run_on_complete().unwrap().unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "test 2 1 1\n");
+    }
+
+    #[test]
+    #[ignore = "needs passwordless sudo, run with --ignored"]
+    fn sudo_runs_the_script_as_root() {
+        let script = Script {
+            sudo: true,
+            ..Script::new("whoami", "id -u")
+        };
+        assert_eq!(script.resolved_command().split(' ').take(2).collect::<Vec<_>>(), ["sudo", "-n"]);
+
+        let result = script.run();
+        assert_eq!(script.status(&result), Status::Complete);
+        assert_eq!(result.unwrap().stdout, b"0\n");
+    }
+
+    #[test]
+    fn remote_scripts_upload_to_the_remotes_tmp_dir() {
+        let remote: Remote = serde_json::from_str(r#"{"host": "build", "tmp_dir": "/var/tmp/ci/"}"#).unwrap();
+        let script = Script::builder("deploy", "make deploy").destination(Destination::Remote(remote)).build();
+        let file = "/var/tmp/ci/checkmate_<id>_deploy.sh";
+        let plan = script.plan();
+        assert_eq!(plan.first().unwrap(), &format!("ssh build cat > {file} < <script>"));
+        assert_eq!(plan.last().unwrap(), &format!("ssh build rm -f {file}"));
+        assert!(script.resolved_command().ends_with(file), "{}", script.resolved_command());
+    }
+
+    #[test]
+    fn script_files_run_like_inline_scripts() {
+        let dir = temp_dir("script-file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let body = "echo \"args: $#\"\necho err >&2\nexit 3";
+        let path = dir.join("step.sh");
+        std::fs::write(&path, body).unwrap();
+
+        let inline = Script::new("step", body);
+        let from_file = Script {
+            script_file: Some(path.display().to_string()),
+            ..Script::new("step", "")
+        };
+        assert_eq!(from_file.text().unwrap(), inline.text().unwrap());
+        let inline_result = inline.run();
+        let file_result = from_file.run();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(inline.status(&inline_result), Status::Failed(3));
+        assert_eq!(from_file.status(&file_result), Status::Failed(3));
+        let (inline, from_file) = (inline_result.unwrap(), file_result.unwrap());
+        assert_eq!(from_file.stdout, inline.stdout);
+        assert_eq!(from_file.stderr, inline.stderr);
+        assert_eq!(from_file.stdout, b"args: 0\n");
+    }
+
+    #[test]
+    fn remotes_round_trip_through_serde() {
+        let destination: Destination =
+            serde_json::from_str(r#"{"Remote": {"host": "build", "user": "ci", "port": 2222}}"#).unwrap();
+        let Destination::Remote(remote) = &destination else {
+            panic!("{destination:?}")
+        };
+        assert_eq!((remote.host.as_str(), remote.user.as_deref(), remote.port), ("build", Some("ci"), Some(2222)));
+        assert_eq!(remote.to_string(), "ci@build:2222");
+
+        let json = serde_json::to_string(&destination).unwrap();
+        assert_eq!(serde_json::from_str::<Destination>(&json).unwrap(), destination);
+
+        // The short form reads the same as the full one
+        let short: Remote = serde_json::from_str(r#""ci@build""#).unwrap();
+        let full: Remote = serde_json::from_str(r#"{"host": "build", "user": "ci"}"#).unwrap();
+        assert_eq!(short, full);
+        assert_eq!(serde_json::from_str::<Remote>(&serde_json::to_string(&short).unwrap()).unwrap(), short);
+    }
+
+    #[test]
+    #[ignore = "needs key based ssh to localhost, run with --ignored"]
+    fn artifacts_are_copied_back_after_the_script_succeeds() {
+        let dir = temp_dir("artifacts");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (built, copied) = (dir.join("built.txt"), dir.join("copied.txt"));
+        let script = Script {
+            download: vec![Artifact {
+                remote: built.display().to_string(),
+                local: copied.display().to_string(),
+            }],
+            known_hosts: KnownHostsPolicy::Accept,
+            ..Script::builder("build", format!("printf 'built\\n' > {}", built.display()))
+                .destination(Destination::Remote("localhost".into()))
+                .build()
+        };
+        let result = script.run();
+        assert_eq!(script.status(&result), Status::Complete);
+        assert_eq!(std::fs::read(&copied).unwrap(), b"built\n");
+
+        // An artifact that was never made fails the download, not the script
+        let missing = Script {
+            script: "true".into(),
+            download: vec![Artifact {
+                remote: dir.join("missing.txt").display().to_string(),
+                local: copied.display().to_string(),
+            }],
+            ..script
+        };
+        let result = missing.run();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(missing.status(&result), Status::DownloadFailed);
+    }
+
+    #[test]
+    fn multi_script_tasks_report_their_first_unsuccessful_script() {
+        let scripts = || {
+            vec![Script::new("build", "make"), Script::new("test", "make test"), Script::new("lint", "make lint")]
+        };
+
+        let serial = Task::Serial(scripts());
+        assert_eq!(serial.status(&TaskResult::Serial(vec![exited(0), exited(0), exited(0)])), Status::Complete);
+        assert_eq!(serial.status(&TaskResult::Serial(vec![exited(0), exited(2), exited(1)])), Status::Failed(2));
+        let parallel = Task::Parallel(scripts());
+        let cancelled = TaskResult::Parallel(vec![exited(0), Err(Cancelled.into()), exited(1)]);
+        assert_eq!(parallel.status(&cancelled), Status::Cancelled);
+
+        // A script still running keeps the whole task in progress, however the others ended
+        let runner = Job::new("test", vec![Task::Parallel(vec![
+            Script::new("done", "true"),
+            Script::new("broken", "exit 3"),
+            Script::new("hang", "sleep 10"),
+        ])])
+        .run();
+        while runner.threads[0].started().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(runner.threads[0].status(), Status::InProgress);
+        runner.cancel_all();
+        runner.wait_all().unwrap_err();
+        assert_eq!(runner.threads[0].status(), Status::Failed(3));
+    }
+
+    #[test]
+    #[ignore = "needs key based ssh to localhost and --test-threads=1, run with --ignored"]
+    fn remote_runs_keep_the_open_files_bounded() {
+        let fds = || std::fs::read_dir("/proc/self/fd").unwrap().count();
+        let script = Script {
+            known_hosts: KnownHostsPolicy::Accept,
+            ..Script::builder("echo", "echo hi").destination(Destination::Remote("localhost".into())).build()
+        };
+        let before = fds();
+        let control = Control::default();
+        let result = control.run(&script, &Progress::default());
+        assert_eq!(script.status(&result), Status::Complete);
+        let connected = fds();
+
+        for _ in 0..50 {
+            let result = control.run(&script, &Progress::default());
+            assert_eq!(script.status(&result), Status::Complete);
+        }
+        assert_eq!(fds(), connected, "runs over the shared session leaked files");
+        drop(control);
+        assert_eq!(fds(), before, "dropping the session pool left files open");
+    }
+
+    #[test]
+    fn validate_rejects_jobs_that_would_fail_once_running() {
+        let invalid = |script: Script| {
+            Job::new("test", vec![Task::Script(script)]).validate().unwrap_err().to_string()
+        };
+
+        assert!(Job::new("test", vec![Task::local("ok", "true")]).validate().is_ok());
+        let unnamed = Job::new("test", vec![Task::Serial(vec![Script::new("build", "make"), Script::new("", "true")])]);
+        assert_eq!(unnamed.validate().unwrap_err().to_string(), "A script in build =>  has no name");
+        assert_eq!(
+            invalid(Script {
+                stdin: Some("input".into()),
+                delivery: ScriptDelivery::Stdin,
+                ..Script::new("piped", "cat")
+            }),
+            "piped is delivered over stdin, it can't also take stdin input"
+        );
+        assert_eq!(
+            invalid(Script {
+                script_file: Some("build.sh".into()),
+                ..Script::new("build", "make")
+            }),
+            "build sets both script and script_file"
+        );
+        assert_eq!(
+            invalid(Script::builder("deploy", "true").destination(Destination::Group("web".into())).build()),
+            "deploy targets inventory group web, pass --inventory to resolve it"
+        );
+    }
+
+    /// A finished run's output, exiting with `code`
+    fn exited(code: i32) -> Result<Output> {
+        use std::os::unix::process::ExitStatusExt;
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: vec![],
+            stderr: vec![],
+        })
+    }
+
+    #[test]
+    fn a_thread_is_queued_then_in_progress_then_complete() {
+        let (tx, rx) = channel(Err(anyhow!("No data")));
+        let thread = JobThread::from_parts(Task::local("build", "make"), rx);
+        assert_eq!(thread.status(), Status::Queued);
+        assert!(thread.is_queued() && !thread.is_finished());
+
+        *thread.started.lock().unwrap() = Some(Instant::now());
+        assert_eq!(thread.status(), Status::InProgress);
+        assert!(!thread.is_queued() && !thread.is_finished());
+
+        tx.send(Ok(TaskResult::Script(exited(0)))).unwrap();
+        assert_eq!(thread.status(), Status::Complete);
+        assert!(thread.is_finished());
+    }
+
+    #[test]
+    fn a_thread_keeps_the_error_its_script_ended_with() {
+        let (tx, rx) = channel(Err(anyhow!("No data")));
+        let task = Task::Serial(vec![Script::new("a", "true"), Script::new("b", "true")]);
+        let thread = JobThread::from_parts(task, rx);
+        *thread.started.lock().unwrap() = Some(Instant::now());
+
+        tx.send(Ok(TaskResult::Serial(vec![exited(0), Err(anyhow!("Connection refused"))]))).unwrap();
+        assert_eq!(thread.status(), Status::Error);
+        assert!(thread.status_line().starts_with("a => b: ERROR"), "{}", thread.status_line());
+        let result = thread.thread.borrow();
+        let outputs = result.as_ref().unwrap().outputs();
+        assert!(outputs[0].is_ok());
+        assert_eq!(outputs[1].as_ref().unwrap_err().to_string(), "Connection refused");
+    }
+
+    #[test]
+    fn output_builds_up_in_progress_as_it_arrives() {
+        let script = Script::new("build", "make");
+        let progress = Progress::default();
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let live = || progress.stdout.lock().unwrap().clone();
+
+        let output = Runtime::new().unwrap().block_on(async {
+            let feed = async {
+                for line in ["one\n", "two\n"] {
+                    let before = live().len();
+                    writer.write_all(line.as_bytes()).await.unwrap();
+                    while live().len() == before {
+                        tokio::task::yield_now().await;
+                    }
+                    assert!(live().ends_with(line.as_bytes()));
+                }
+                drop(writer);
+            };
+            let status = async { Ok::<_, std::io::Error>(exited(0).unwrap().status) };
+            tokio::join!(feed, script.capture(Some(reader), None::<&[u8]>, status, &progress)).1
+        });
+
+        assert_eq!(live(), b"one\ntwo\n");
+        assert_eq!(output.unwrap().stdout, b"one\ntwo\n");
+    }
+
+    /// Everything a test's subscriber writes
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
         }
     }
+
+    #[test]
+    fn runs_trace_their_spawn_completion_and_errors() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let missing = Script::builder("missing", "true")
+            .shell(Shell::Custom("checkmate-no-such-shell".into()))
+            .build();
+        tracing::subscriber::with_default(subscriber, || {
+            Script::new("greet", "echo hi").run().unwrap();
+            missing.run().unwrap_err();
+        });
+
+        let logs = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let line = |span: &str, event: &str| logs.lines().find(|l| l.contains(span) && l.contains(event));
+        let spawned = line("run_local{script=greet}", "spawned bash ").expect(&logs);
+        assert!(spawned.contains("DEBUG") && spawned.contains("pid=Some("), "{spawned}");
+        assert!(line("run_local{script=greet}", "complete, exit status: 0").is_some(), "{logs}");
+        let failed = line("run_local{script=missing}", "error=").expect(&logs);
+        assert!(failed.contains("ERROR") && failed.contains("checkmate-no-such-shell"), "{failed}");
+        assert!(line("run_local{script=missing}", "spawned").is_none(), "{logs}");
+    }
 }
@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Result};
+use checkmate::Job;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+/// Jobs indexed by name, from a directory of job files or a single file holding several jobs
+pub struct Library {
+    jobs: BTreeMap<String, Job>,
+}
+
+impl Library {
+    /// Files in a directory that don't parse as jobs are skipped with a warning,
+    /// so a library can sit next to other config. A single file has to parse.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut jobs = BTreeMap::new();
+
+        let is_dir = path.is_dir();
+        let files = if is_dir {
+            let mut files = vec![];
+            for entry in std::fs::read_dir(path)? {
+                let file = entry?.path();
+                if file
+                    .extension()
+                    .is_some_and(|e| ["json", "dhall", "yaml", "yml", "toml"].iter().any(|x| e == *x))
+                {
+                    files.push(file);
+                }
+            }
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        for file in files {
+            let loaded = match load_jobs(&file) {
+                Ok(loaded) => loaded,
+                Err(e) if is_dir => {
+                    tracing::warn!("Skipping {e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            for job in loaded {
+                jobs.insert(job.name.clone(), job);
+            }
+        }
+
+        Ok(Self { jobs })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.jobs.keys()
+    }
+
+    pub fn get(&self, name: &str) -> Result<Job> {
+        self.jobs.get(name).cloned().ok_or_else(|| {
+            anyhow!(
+                "No job named {name}, available: {}",
+                self.names().cloned().collect::<Vec<String>>().join(", ")
+            )
+        })
+    }
+}
+
+/// The jobs in one library file, a JSON file may hold a list of them
+fn load_jobs(file: &Path) -> Result<Vec<Job>> {
+    if !is_json(file) {
+        return Ok(vec![load_file(file)?]);
+    }
+    let contents = std::fs::read_to_string(file)
+        .map_err(|e| anyhow!("Failed to open {}: {e}", file.display()))?;
+    // Picked up front rather than with an untagged enum, which would
+    // hide where the file went wrong
+    let parsed = if contents.trim_start().starts_with('[') {
+        serde_json::from_str(&contents)
+    } else {
+        serde_json::from_str(&contents).map(|one| vec![one])
+    };
+    parsed.map_err(|e| anyhow!("Failed to parse {}: {e}", file.display()))
+}
+
+pub fn parse_job(reader: impl Read) -> Result<Job> {
+    serde_json::from_reader(reader).map_err(|e| anyhow!("Failed to parse job: {e}"))
+}
+
+/// Load a job file by its extension.
+/// Anything that isn't Dhall, YAML or TOML is read as JSON.
+pub fn load_file(path: &Path) -> Result<Job> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    let read = || {
+        std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Failed to open job file {}: {e}", path.display()))
+    };
+    match extension {
+        // Without the static type annotation, which would make every field
+        // mandatory, so fields left out get their serde defaults like in JSON
+        "dhall" => serde_dhall::from_file(path)
+            .parse()
+            .map_err(|e| anyhow!("Failed to parse {}: {e}", path.display())),
+        // Through JSON's data model so tasks and destinations are written as
+        // single key maps like in JSON, rather than with YAML tags
+        "yaml" | "yml" => serde_yaml::from_str::<serde_json::Value>(&read()?)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| Ok(serde_json::from_value(value)?))
+            .map_err(|e| anyhow!("Failed to parse {}: {e}", path.display())),
+        "toml" => {
+            toml::from_str(&read()?).map_err(|e| anyhow!("Failed to parse {}: {e}", path.display()))
+        }
+        _ => parse_job(read()?.as_bytes()),
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dhall_job_matches_json_with_defaults() {
+        let dir = std::env::temp_dir().join(format!("checkmate-library-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dhall = dir.join("hello.dhall");
+        std::fs::write(
+            &dhall,
+            r#"
+let Destination = < Local | Remote : { host : Text } >
+let Environment = < `None` | Current >
+let Shell = < Bash | Custom : Text >
+let Script = { name : Text, destination : Destination, environment : Environment, shell : Shell, script : Text }
+let hello = { name = "hello", destination = Destination.Local, environment = Environment.Current, shell = Shell.Bash, script = "echo hello" }
+in  { name = "hello", tasks = [ < Script : Script >.Script hello ] }
+"#,
+        )
+        .unwrap();
+        let json = r#"{"name": "hello", "tasks": [{"Script": {"name": "hello", "destination": "Local",
+            "environment": "Current", "shell": "Bash", "script": "echo hello"}}]}"#;
+
+        let from_dhall = load_file(&dhall).unwrap();
+        let from_json = parse_job(json.as_bytes()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            serde_json::to_value(&from_dhall).unwrap(),
+            serde_json::to_value(&from_json).unwrap()
+        );
+    }
+
+    #[test]
+    fn parses_a_job_from_a_reader() {
+        let json = r#"{"name": "piped", "tasks": [{"Script": {"name": "hi", "destination": "Local",
+            "environment": "Current", "shell": "Bash", "script": "echo hi"}}]}"#;
+        let job = parse_job(std::io::Cursor::new(json)).unwrap();
+        assert_eq!(job.name, "piped");
+        assert_eq!(job.tasks.len(), 1);
+
+        let error = parse_job("{".as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("Failed to parse job:"), "{error}");
+    }
+
+    #[test]
+    fn resolves_jobs_by_name_from_a_directory() {
+        let dir = std::env::temp_dir().join(format!("checkmate-names-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let job = |name: &str| {
+            format!(
+                r#"{{"name": "{name}", "tasks": [{{"Script": {{"name": "{name}", "destination": "Local",
+                    "environment": "Current", "shell": "Bash", "script": "echo {name}"}}}}]}}"#
+            )
+        };
+        std::fs::write(dir.join("deploy.json"), job("deploy")).unwrap();
+        std::fs::write(dir.join("checks.json"), format!("[{}, {}]", job("lint"), job("test"))).unwrap();
+        std::fs::write(dir.join("broken.json"), "{").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a job").unwrap();
+
+        let library = Library::load(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let library = library.unwrap();
+        assert_eq!(library.names().collect::<Vec<_>>(), ["deploy", "lint", "test"]);
+        assert_eq!(library.get("lint").unwrap().name, "lint");
+        assert_eq!(
+            library.get("missing").unwrap_err().to_string(),
+            "No job named missing, available: deploy, lint, test"
+        );
+    }
+
+    #[test]
+    fn yaml_and_toml_jobs_match_json() {
+        let dir = std::env::temp_dir().join(format!("checkmate-formats-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("hello.yaml"),
+            r#"
+name: hello
+timeout: 30
+tasks:
+  - Script:
+      name: hello
+      destination: Local
+      environment: Current
+      shell: Bash
+      script: echo hello
+"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("hello.toml"),
+            r#"
+name = "hello"
+timeout = 30
+
+[[tasks]]
+[tasks.Script]
+name = "hello"
+destination = "Local"
+environment = "Current"
+shell = "Bash"
+script = "echo hello"
+"#,
+        )
+        .unwrap();
+        let json = r#"{"name": "hello", "timeout": 30, "tasks": [{"Script": {"name": "hello", "destination": "Local",
+            "environment": "Current", "shell": "Bash", "script": "echo hello"}}]}"#;
+
+        let from_yaml = load_file(&dir.join("hello.yaml"));
+        let from_toml = load_file(&dir.join("hello.toml"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let from_json = serde_json::to_value(parse_job(json.as_bytes()).unwrap()).unwrap();
+        assert_eq!(serde_json::to_value(from_yaml.unwrap()).unwrap(), from_json);
+        assert_eq!(serde_json::to_value(from_toml.unwrap()).unwrap(), from_json);
+    }
+
+    #[test]
+    fn parse_errors_name_the_problem_and_where_it_is() {
+        let error = |lines: &[&str]| parse_job(lines.join("\n").as_bytes()).unwrap_err().to_string();
+
+        assert_eq!(
+            error(&[r#"{"name": "ci", "tasks": ["#, r#"{"Scrpt": {}}]}"#]),
+            "Failed to parse job: unknown variant `Scrpt`, expected one of `Script`, `Serial`, `Parallel` \
+             at line 2 column 8"
+        );
+        assert_eq!(
+            error(&[r#"{"tasks": ["#, r#"]}"#]),
+            "Failed to parse job: missing field `name` at line 2 column 2"
+        );
+        assert_eq!(
+            error(&[
+                r#"{"name": "ci", "tasks": [{"Script": {"name": "lint","#,
+                r#""destination": "Lcal", "environment": "Current", "shell": "Bash"}}]}"#,
+            ]),
+            "Failed to parse job: unknown variant `Lcal`, expected one of `Local`, `Remote`, `Group` \
+             at line 2 column 21"
+        );
+    }
+}
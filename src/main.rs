@@ -1,34 +1,202 @@
 use anyhow::{anyhow, Result};
-use checkmate::{Destination, Job, Script, Task};
-use clap::Parser;
+use checkmate::{Destination, Inventory, Job, Script, Status, Task};
+use clap::{Parser, Subcommand};
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use std::io::Write;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
-use std::{io, thread, time::Duration};
+use std::{io, time::Duration};
+use tracing::Subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
 use tui::{backend::CrosstermBackend, Terminal};
 
 mod draw;
 use draw::*;
+mod headless;
+use headless::OutputFormat;
+mod history;
+mod library;
+use library::{load_file, parse_job, Library};
+mod ws;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Directory of job files, or a file holding a list of jobs
+    #[arg(long, env = "CHECKMATE_LIBRARY", default_value = ".")]
+    library: PathBuf,
+
+    /// Path to the job file, read as Dhall, YAML, TOML or JSON by its extension. `-` reads JSON from stdin
     #[arg(short, long)]
     job: Option<String>,
 
+    /// Read the job from stdin
+    #[arg(long, default_value_t = false)]
+    stdin: bool,
+
     #[arg(long, default_value_t = false)]
     generate_json_schema: bool,
 
+    /// Write the JSON schema to a file instead of stdout
+    #[arg(long)]
+    schema_out: Option<PathBuf>,
+
     #[arg(long, default_value_t = false)]
     generate_test_data: bool,
+
+    /// INI or YAML file of host groups that Group destinations are resolved against
+    #[arg(long, env = "CHECKMATE_INVENTORY")]
+    inventory: Option<PathBuf>,
+
+    /// Exit with an error when the job has no scripts to run, always on for --headless
+    #[arg(long, default_value_t = false)]
+    fail_on_no_tasks: bool,
+
+    /// Print the commands each script would run, without running them
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Print the job's tasks without running them
+    #[arg(long, default_value_t = false)]
+    list: bool,
+
+    /// Print the job as JSON after inventory groups are resolved, then exit
+    #[arg(long, default_value_t = false)]
+    print_effective: bool,
+
+    /// Run without the TUI, exiting non-zero if any task fails
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+
+    /// What a headless run prints, anything but `text` implies --headless
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Write a JUnit XML report here once the job is done, implies --headless
+    #[arg(long)]
+    junit: Option<PathBuf>,
+
+    /// Milliseconds between redraws once nothing has changed for a while
+    #[arg(long, default_value_t = 1000)]
+    idle_tick_ms: u64,
+
+    /// Seconds the whole job may take, overrides the job's own timeout
+    #[arg(long)]
+    job_timeout: Option<u64>,
+
+    /// Check the job file and exit without running it
+    #[arg(long, default_value_t = false)]
+    validate: bool,
+
+    /// Quit the TUI once every task has finished, exiting non-zero if any failed
+    #[arg(long, default_value_t = false)]
+    auto_exit: bool,
+
+    /// Run the tasks that failed once more in a headless run, the TUI reruns them with <r>
+    #[arg(long, default_value_t = false)]
+    rerun_failed: bool,
+
+    /// Only run tasks with a script tagged with one of these, e.g. --tags lint,test
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Leave out tasks with a script tagged with one of these
+    #[arg(long, value_delimiter = ',')]
+    skip_tags: Vec<String>,
+
+    /// Run without asking first, jobs with remote scripts or `confirm` set ask otherwise
+    #[arg(short, long, default_value_t = false)]
+    yes: bool,
+
+    /// Print the last N runs from the history and exit
+    #[arg(long, num_args = 0..=1, default_missing_value = "10", value_name = "N")]
+    history: Option<usize>,
+
+    /// Where each finished run is recorded, defaults to history.jsonl in the user's data directory
+    #[arg(long, env = "CHECKMATE_HISTORY")]
+    history_file: Option<PathBuf>,
+
+    /// Append log messages to this file, filtered by RUST_LOG. Headless runs log
+    /// to stderr without it, the TUI doesn't log without it.
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Serve a websocket on this port of localhost that streams each task's output and
+    /// status changes as JSON
+    #[arg(long)]
+    ws_port: Option<u16>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Run a job from the library by name
+    Run { name: String },
+    /// List the jobs in the library
+    List,
+}
+
+/// Redraw quickly while tasks are running or keys are pressed, back off once idle
+struct Ticker {
+    fast: Duration,
+    slow: Duration,
+    idle_after: Duration,
+    last_activity: Instant,
+}
+
+impl Ticker {
+    fn new(fast: Duration, slow: Duration, idle_after: Duration) -> Self {
+        Self {
+            fast,
+            slow,
+            idle_after,
+            last_activity: Instant::now(),
+        }
+    }
+
+    fn activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    fn rate(&self) -> Duration {
+        if self.last_activity.elapsed() >= self.idle_after {
+            self.slow.max(self.fast)
+        } else {
+            self.fast
+        }
+    }
+
+    /// How long to wait for input before the tick after `last_tick` is due.
+    /// The loop's only wait, a key that arrives meanwhile is handled straight away.
+    fn until_tick(&self, last_tick: Instant) -> Duration {
+        self.rate().saturating_sub(last_tick.elapsed())
+    }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let headless = args.headless || args.output != OutputFormat::Text || args.junit.is_some();
+    // `list` never draws the TUI, so its warnings can go to stderr
+    let listing = matches!(args.command, Some(Commands::List));
+    init_logging(args.log_file.as_deref(), headless || listing)?;
+
+    if let Some(path) = &args.schema_out {
+        let schema = schemars::schema_for!(Job);
+        let mut file = std::fs::File::create(path)
+            .map_err(|e| anyhow!("Failed to create {}: {e}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string_pretty(&schema)?)?;
+        return Ok(());
+    }
 
     if args.generate_json_schema {
         let schema = schemars::schema_for!(Job);
@@ -40,104 +208,638 @@ fn main() -> Result<()> {
         return generate_test_data();
     }
 
-    let job: Job = serde_json::from_reader(
-        std::fs::File::open(args.job.unwrap()).expect("Failed to open job file"),
-    )
-    .expect("Failed to parse job");
+    let history_path = args.history_file.clone().or_else(history::default_path);
+    if let Some(count) = args.history {
+        let path = history_path.ok_or_else(|| anyhow!("No home directory to keep history in"))?;
+        for run in history::last(&path, count)? {
+            println!("{}", run.line());
+        }
+        return Ok(());
+    }
 
-    // setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    let job = match &args.command {
+        Some(Commands::List) => {
+            for name in Library::load(&args.library)?.names() {
+                println!("{name}");
+            }
+            return Ok(());
+        }
+        Some(Commands::Run { name }) => Library::load(&args.library)?.get(name)?,
+        None => load_job(&args)?,
+    };
+
+    let job = effective(job, &args)?;
+    if args.validate {
+        println!("{}: OK", job.name);
+        return Ok(());
+    }
+
+    check_selected(&job, args.fail_on_no_tasks || headless)?;
+
+    if args.list {
+        let width = job.tasks.iter().map(|t| t.name().chars().count()).max().unwrap_or(0);
+        for task in &job.tasks {
+            println!("{:<width$}  {}", task.name(), task.type_name());
+        }
+        return Ok(());
+    }
+
+    if args.dry_run {
+        for task in &job.tasks {
+            println!("== {} [{}]", task.name(), task.type_name());
+            for script in task.scripts() {
+                println!("-- {} on {}", script.name, script.destination);
+                for step in script.plan() {
+                    println!("   {step}");
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if args.print_effective {
+        println!("{}", serde_json::to_string_pretty(&job)?);
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    let terminal = stdin.is_terminal() && !job_from_stdin(&args);
+    if !args.yes && !confirmed(&job, terminal.then(|| stdin.lock()), io::stderr())? {
+        return Err(anyhow!("{} was not run, pass --yes to skip the prompt", job.name));
+    }
+
+    let broadcaster = args.ws_port.map(ws::Broadcaster::bind).transpose()?;
+    if let Some(broadcaster) = &broadcaster {
+        tracing::info!("Streaming events to ws://{}", broadcaster.addr());
+    }
+
+    if headless {
+        std::process::exit(if headless::run(
+            job,
+            args.output,
+            args.junit.as_deref(),
+            args.rerun_failed,
+            history_path.as_deref(),
+            broadcaster,
+        ) { 0 } else { 1 });
+    }
+
+    // setup terminal, restored when the guard drops even on errors and panics
+    let guard = TerminalGuard::new()?;
+    let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
-    let tick_rate = Duration::from_millis(100);
+    // Set when resumed after being suspended, the terminal needs setting up again
+    let resumed = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGCONT, resumed.clone())?;
+    let mut ticker = Ticker::new(
+        Duration::from_millis(100),
+        Duration::from_millis(args.idle_tick_ms),
+        Duration::from_secs(3),
+    );
     let mut last_tick = Instant::now();
 
-    let runner = job.run();
+    let mut runner = job.run();
+    if let Some(broadcaster) = &broadcaster {
+        broadcaster.watch(&runner);
+    }
     let mut state = State::default();
+    let mut finished = runner.finished();
+    let mut on_complete = None;
 
-    loop {
-        let timeout = tick_rate
-            .checked_sub(last_tick.elapsed())
-            .unwrap_or_else(|| Duration::from_secs(0));
+    // Draw the first frame straight away, then on ticks, keys and finished tasks
+    let mut redraw = true;
 
-        if crossterm::event::poll(timeout)? {
+    loop {
+        if crossterm::event::poll(ticker.until_tick(last_tick))? {
             if let Event::Key(key) = event::read()? {
+                ticker.activity();
+                redraw = true;
                 if KeyCode::Char('c') == key.code && key.modifiers == KeyModifiers::CONTROL {
                     break;
                 }
+                // Raw mode swallows the suspend signal, so send it ourselves
+                if KeyCode::Char('z') == key.code && key.modifiers == KeyModifiers::CONTROL {
+                    leave_terminal(terminal.backend_mut())?;
+                    signal_hook::low_level::raise(signal_hook::consts::SIGTSTP)?;
+                    continue;
+                }
+                // Typed keys go to the search box rather than being commands
+                let searching = state.searching;
+                if searching {
+                    state.search_input(key.code, &runner);
+                } else if KeyCode::Char('q') == key.code {
+                    break;
+                }
                 match key.code {
+                    _ if searching => (),
                     KeyCode::Up => {
                         state.up_key();
                     }
                     KeyCode::Down => {
-                        state.down_key(runner.job.tasks.len() - 1);
+                        state.down_key(state.visible(&runner).len().saturating_sub(1));
                     }
                     KeyCode::Enter => {
                         state.enter_key();
                     }
-                    KeyCode::Esc | KeyCode::Backspace => {
+                    // In the job view Esc clears the search first
+                    KeyCode::Esc | KeyCode::Backspace
+                        if !matches!(state.draw_mode, DrawMode::Job) || !state.clear_search() =>
+                    {
                         state.back_key();
                     }
+                    KeyCode::Char('e') => {
+                        state.expand_key();
+                    }
+                    KeyCode::Tab => {
+                        state.tab_key();
+                    }
+                    KeyCode::PageUp => {
+                        state.page_up_key();
+                    }
+                    KeyCode::PageDown => {
+                        state.page_down_key();
+                    }
+                    KeyCode::Char(' ') => {
+                        runner.control.toggle_pause();
+                    }
+                    KeyCode::Char('y') => {
+                        state.copy_key(&runner);
+                    }
+                    KeyCode::Char('d') => {
+                        state.destination_key(&runner);
+                    }
+                    KeyCode::Char('t') => {
+                        state.tag_key(&runner);
+                    }
+                    KeyCode::Char('/') => {
+                        state.search_key();
+                    }
+                    KeyCode::Char('x') => {
+                        state.cancel_key(&runner);
+                    }
+                    // Only once the job is done, so the hook has described the first run
+                    KeyCode::Char('r')
+                        if finished == runner.threads.len() && runner.counts().1 > 0 =>
+                    {
+                        join_hook(on_complete.take());
+                        let rerun = runner.failed_job().run();
+                        if let Some(broadcaster) = &broadcaster {
+                            broadcaster.watch(&rerun);
+                        }
+                        runner.merge_rerun(rerun);
+                        finished = runner.finished();
+                        state.rerun_key();
+                    }
                     _ => (),
                 }
             }
         }
-        if last_tick.elapsed() >= tick_rate {
+        if last_tick.elapsed() >= ticker.rate() {
             last_tick = Instant::now();
+            state.tick();
+            redraw = true;
+        }
+        if runner.finished() != finished {
+            finished = runner.finished();
+            ticker.activity();
+            redraw = true;
+        }
+        // A running task can print at any time and its spinner turns, so only back off
+        // once nothing is running
+        if runner.threads.iter().any(|jr| jr.status() == Status::InProgress) {
+            ticker.activity();
+        }
+        // Off the UI thread, the hook may be slow or remote
+        if on_complete.is_none() && finished == runner.threads.len() {
+            record(history_path.as_deref(), &runner);
+            let runner = runner.clone();
+            on_complete = Some(std::thread::spawn(move || runner.run_on_complete()));
         }
 
-        terminal.draw(|f| state.draw(f, &runner))?;
-        thread::sleep(Duration::from_millis(100));
+        if on_resume(&resumed, || {
+            enter_terminal(terminal.backend_mut())?;
+            Ok(terminal.clear()?)
+        })? {
+            redraw = true;
+        }
+
+        if redraw {
+            terminal.draw(|f| state.draw(f, &runner))?;
+            redraw = false;
+        }
+
+        // Queued tasks aren't finished, so this waits for them too
+        if args.auto_exit && finished == runner.threads.len() {
+            break;
+        }
+    }
+
+    // Let a running hook finish rather than kill it on exit
+    join_hook(on_complete);
+    if let Some(broadcaster) = broadcaster {
+        broadcaster.close();
+    }
+
+    if args.auto_exit && runner.counts().1 > 0 {
+        // exit skips destructors, restore the terminal first
+        drop(guard);
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Ask on `input` before running a job with remote scripts, or any job with `confirm` set,
+/// prompting on `output`. Anything but yes, including no answer on a closed input, declines.
+/// Without an `input`, when stdin isn't a terminal, a job that needs asking is refused.
+fn confirmed(job: &Job, input: Option<impl BufRead>, mut output: impl Write) -> Result<bool> {
+    let remotes = job.remotes();
+    if remotes.is_empty() && !job.confirm {
+        return Ok(true);
+    }
+    let Some(mut input) = input else {
+        return Err(anyhow!("stdin is not a terminal, pass --yes"));
+    };
+    match remotes.len() {
+        0 => writeln!(output, "{} runs locally", job.name)?,
+        _ => {
+            writeln!(output, "{} runs on:", job.name)?;
+            for remote in remotes {
+                writeln!(output, "  {remote}")?;
+            }
+        }
+    }
+    write!(output, "Run it? [y/N] ")?;
+    output.flush()?;
+    let mut answer = String::new();
+    if input.read_line(&mut answer)? == 0 {
+        // Closed input, end the prompt's line
+        writeln!(output)?;
+    }
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Add a finished run to the history, failing to write it is only logged
+fn record(path: Option<&Path>, runner: &checkmate::JobRunner) {
+    if let Some(path) = path {
+        if let Err(e) = history::append(path, runner) {
+            tracing::warn!("Failed to record run: {e}");
+        }
+    }
+}
+
+/// Log to `path`, or to stderr when there's no TUI to draw over
+fn init_logging(path: Option<&Path>, headless: bool) -> Result<()> {
+    if let Some(subscriber) = subscriber(path, headless)? {
+        subscriber.try_init().map_err(|e| anyhow!("Failed to set up logging: {e}"))?;
+    }
+    Ok(())
+}
+
+/// The subscriber [`init_logging`] installs, none when logs would draw over the TUI.
+/// `RUST_LOG` filters what's logged, only errors when it's unset.
+fn subscriber(path: Option<&Path>, headless: bool) -> Result<Option<impl Subscriber + Send + Sync>> {
+    let writer = match path {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow!("Failed to open log file {}: {e}", path.display()))?;
+            BoxMakeWriter::new(std::sync::Mutex::new(file))
+        }
+        None if headless => BoxMakeWriter::new(io::stderr),
+        None => return Ok(None),
+    };
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::ERROR.into())
+        .from_env_lossy();
+    Ok(Some(
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_ansi(path.is_none())
+            .with_writer(writer)
+            .finish(),
+    ))
+}
+
+fn join_hook(hook: Option<std::thread::JoinHandle<Option<Result<std::process::Output>>>>) {
+    if let Some(Ok(Some(Err(e)))) = hook.map(|hook| hook.join()) {
+        tracing::error!("on_complete failed: {e}");
+    }
+}
+
+/// Puts the terminal back the way the shell expects it when dropped
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> Result<Self> {
+        enter_terminal(&mut io::stdout())?;
+        // The panic message is printed before the guard drops, restore the terminal first so
+        // it ends up on the shell's screen. Task threads catch their panics, leave those be.
+        let ui = std::thread::current().id();
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if std::thread::current().id() == ui {
+                Self::restore();
+            }
+            hook(info);
+        }));
+        Ok(Self)
+    }
+
+    fn restore() {
+        let mut stdout = io::stdout();
+        let _ = leave_terminal(&mut stdout);
+        let _ = execute!(stdout, crossterm::cursor::Show);
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        Self::restore();
+    }
+}
+
+/// Raw mode on the alternate screen, as the TUI needs it
+fn enter_terminal(out: &mut impl Write) -> Result<()> {
+    enable_raw_mode()?;
+    execute!(out, EnterAlternateScreen, EnableMouseCapture)?;
+    Ok(())
+}
+
+/// Set the terminal up again with `restore` once `resumed` was flagged by SIGCONT,
+/// returns whether it did so the screen gets redrawn
+fn on_resume(resumed: &AtomicBool, restore: impl FnOnce() -> Result<()>) -> Result<bool> {
+    if !resumed.swap(false, Ordering::SeqCst) {
+        return Ok(false);
     }
+    restore()?;
+    Ok(true)
+}
 
-    // restore terminal
+/// Hand the terminal back to the shell
+fn leave_terminal(out: &mut impl Write) -> Result<()> {
     disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    execute!(out, LeaveAlternateScreen, DisableMouseCapture)?;
+    Ok(())
+}
+
+/// The job as it runs: inventory groups resolved, host defaults merged in, the timeout
+/// overridden and tasks picked by tag, failing if it isn't valid
+fn effective(mut job: Job, args: &Args) -> Result<Job> {
+    if let Some(path) = &args.inventory {
+        Inventory::load(path)?.resolve(&mut job)?;
+    }
+    job.apply_destination_defaults();
+    if let Some(secs) = args.job_timeout {
+        job.timeout = Some(secs);
+    }
+    job.validate()?;
+    select_tasks(&mut job, args);
+    Ok(job)
+}
 
+/// Keep the tasks picked by `--tags` and `--skip-tags`
+fn select_tasks(job: &mut Job, args: &Args) {
+    if !args.tags.is_empty() || !args.skip_tags.is_empty() {
+        job.retain_tasks(|task| {
+            (args.tags.is_empty() || task.tags().any(|tag| args.tags.contains(tag)))
+                && !task.tags().any(|tag| args.skip_tags.contains(tag))
+        });
+    }
+}
+
+/// When `required`, refuse a job left with nothing to run, e.g. by a mistyped tag
+fn check_selected(job: &Job, required: bool) -> Result<()> {
+    if required && job.tasks.iter().all(|t| t.scripts().is_empty()) {
+        return Err(anyhow!("No tasks selected in job {}", job.name));
+    }
     Ok(())
 }
 
+/// Whether the job is read from stdin, leaving nothing there to answer a prompt with
+fn job_from_stdin(args: &Args) -> bool {
+    match args.job.as_deref() {
+        Some(path) => path == "-",
+        None => args.stdin,
+    }
+}
+
+/// Read the job from `--job <path>`, or stdin for `--job -` and `--stdin`
+fn load_job(args: &Args) -> Result<Job> {
+    match args.job.as_deref() {
+        Some("-") => parse_job(io::stdin().lock()),
+        Some(path) => load_file(Path::new(path)),
+        None if args.stdin => parse_job(io::stdin().lock()),
+        None => Err(anyhow!("No job given, pass --job <path> or --stdin")),
+    }
+}
+
 fn generate_test_data() -> Result<()> {
-    let test = Job {
-        name: "Test".into(),
-        tasks: vec![
-            Task::Script(Script {
-                name: "local: bash_version".into(),
-                script: "bash --version".into(),
-                ..Default::default()
-            }),
-            Task::Script(Script {
-                name: "znix: bash_version".into(),
-                script: "bash --version".into(),
-                destination: Destination::Remote("zthayer@10.17.68.57".into()),
-                ..Default::default()
-            }),
+    let test = Job::new(
+        "Test",
+        vec![
+            Task::local("local: bash_version", "bash --version"),
+            Task::Script(
+                Script::builder("znix: bash_version", "bash --version")
+                    .destination(Destination::Remote("zthayer@10.17.68.57".into()))
+                    .build(),
+            ),
             Task::Serial(vec![
-                Script {
-                    name: "write".into(),
-                    script: "date >> /tmp/date.tmp".into(),
-                    destination: Destination::Remote("zthayer@10.17.68.57".into()),
-                    ..Default::default()
-                },
-                Script {
-                    name: "read".into(),
-                    script: "cat /tmp/date.tmp".into(),
-                    destination: Destination::Remote("zthayer@10.17.68.57".into()),
-                    ..Default::default()
-                },
+                Script::builder("write", "date >> /tmp/date.tmp")
+                    .destination(Destination::Remote("zthayer@10.17.68.57".into()))
+                    .build(),
+                Script::builder("read", "cat /tmp/date.tmp")
+                    .destination(Destination::Remote("zthayer@10.17.68.57".into()))
+                    .build(),
             ]),
         ],
-    };
+    );
 
     let mut file = std::fs::File::create("test.json")?;
     file.write_all(serde_json::to_string_pretty(&test)?.as_bytes())?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checkmate::Shell;
+
+    #[test]
+    fn ticker_backs_off_when_idle_and_snaps_back_on_activity() {
+        let fast = Duration::from_millis(10);
+        let slow = Duration::from_millis(500);
+        let mut ticker = Ticker::new(fast, slow, Duration::from_millis(20));
+        assert_eq!(ticker.rate(), fast);
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(ticker.rate(), slow);
+        ticker.activity();
+        assert_eq!(ticker.rate(), fast);
+    }
+
+    #[test]
+    fn ticker_never_slows_below_fast() {
+        let fast = Duration::from_millis(200);
+        let ticker = Ticker::new(fast, Duration::from_millis(50), Duration::ZERO);
+        assert_eq!(ticker.rate(), fast);
+    }
+
+    #[test]
+    fn terminal_is_restored_once_per_resume() {
+        let resumed = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(signal_hook::consts::SIGCONT, resumed.clone()).unwrap();
+        let restores = std::cell::Cell::new(0);
+        let restore = || {
+            restores.set(restores.get() + 1);
+            Ok(())
+        };
+
+        assert!(!on_resume(&resumed, restore).unwrap());
+        signal_hook::low_level::raise(signal_hook::consts::SIGCONT).unwrap();
+        assert!(on_resume(&resumed, restore).unwrap());
+        assert!(!on_resume(&resumed, restore).unwrap());
+        assert_eq!(restores.get(), 1);
+
+        // A failed restore is reported, not retried on the next loop
+        signal_hook::low_level::raise(signal_hook::consts::SIGCONT).unwrap();
+        assert!(on_resume(&resumed, || Err(anyhow!("no tty"))).is_err());
+        assert!(!on_resume(&resumed, restore).unwrap());
+    }
+
+    #[test]
+    fn filtering_out_every_task_is_an_error_when_asked() {
+        let job: Job = serde_json::from_value(serde_json::json!({"name": "ci", "tasks": [
+            {"Script": {"name": "lint", "destination": "Local", "environment": "Current",
+                "shell": "Bash", "script": "true", "tags": ["lint"]}},
+        ]}))
+        .unwrap();
+        let selected = |flags: &[&str]| {
+            let args = Args::parse_from([&["checkmate", "--job", "ci.json"], flags].concat());
+            let mut job = job.clone();
+            select_tasks(&mut job, &args);
+            check_selected(&job, args.fail_on_no_tasks).map(|_| job.tasks.len())
+        };
+
+        assert_eq!(selected(&["--tags", "lint", "--fail-on-no-tasks"]).unwrap(), 1);
+        assert_eq!(selected(&["--tags", "lnit"]).unwrap(), 0);
+        assert_eq!(
+            selected(&["--tags", "lnit", "--fail-on-no-tasks"]).unwrap_err().to_string(),
+            "No tasks selected in job ci"
+        );
+        assert!(selected(&["--skip-tags", "lint", "--fail-on-no-tasks"]).is_err());
+    }
+
+    #[test]
+    fn input_wait_ends_at_the_next_tick() {
+        let fast = Duration::from_millis(100);
+        let ticker = Ticker::new(fast, Duration::from_secs(1), Duration::from_secs(3));
+
+        let just_ticked = ticker.until_tick(Instant::now());
+        assert!(just_ticked <= fast && just_ticked > Duration::from_millis(90), "{just_ticked:?}");
+        assert_eq!(ticker.until_tick(Instant::now() - Duration::from_millis(250)), Duration::ZERO);
+
+        // Time spent on keys since the tick comes off the wait rather than adding to it
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(40));
+        let left = ticker.until_tick(start);
+        assert!(left <= Duration::from_millis(60), "{left:?}");
+    }
+
+    #[test]
+    fn logs_go_to_the_log_file() {
+        let path = std::env::temp_dir().join(format!("checkmate-log-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(subscriber(None, false).unwrap().is_none());
+
+        let subscriber = subscriber(Some(&path), false).unwrap().unwrap();
+        // Errors pass the default filter
+        let missing = Script::builder("deploy", "true")
+            .shell(Shell::Custom("checkmate-no-such-shell".into()))
+            .build();
+        tracing::subscriber::with_default(subscriber, || missing.run().unwrap_err());
+        let logged = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(logged.contains("run_local{script=deploy}"), "{logged}");
+        assert!(logged.contains("Shell not found: checkmate-no-such-shell"), "{logged}");
+    }
+
+    #[test]
+    fn only_yes_confirms_a_remote_job() {
+        let job: Job = serde_json::from_value(serde_json::json!({"name": "deploy", "tasks": [
+            {"Script": {"name": "restart", "destination": {"Remote": "ops@web1"},
+                "environment": "Current", "shell": "Bash", "script": "true"}},
+        ]}))
+        .unwrap();
+        let answer = |input: &str| {
+            let mut prompt = vec![];
+            let confirmed = confirmed(&job, Some(input.as_bytes()), &mut prompt).unwrap();
+            (confirmed, String::from_utf8(prompt).unwrap())
+        };
+
+        let (yes, prompt) = answer("y\n");
+        assert!(yes);
+        assert_eq!(prompt, "deploy runs on:\n  ops@web1\nRun it? [y/N] ");
+        assert!(answer("YES\n").0);
+        assert!(!answer("n\n").0);
+        assert!(!answer("\n").0);
+        let (eof, prompt) = answer("");
+        assert!(!eof);
+        assert!(prompt.ends_with("Run it? [y/N] \n"), "{prompt}");
+
+        // Local jobs don't ask unless they opt in
+        let local: Job = serde_json::from_value(serde_json::json!({"name": "lint", "tasks": []})).unwrap();
+        assert!(confirmed(&local, Some("n\n".as_bytes()), io::sink()).unwrap());
+    }
+
+    #[test]
+    fn print_effective_reflects_cli_overrides() {
+        let job: Job = serde_json::from_value(serde_json::json!({"name": "ci", "timeout": 10, "tasks": [
+            {"Script": {"name": "lint", "destination": "Local", "environment": "Current",
+                "shell": "Bash", "script": "true", "tags": ["lint"]}},
+            {"Script": {"name": "test", "destination": "Local", "environment": "Current",
+                "shell": "Bash", "script": "true", "tags": ["test"]}},
+        ]}))
+        .unwrap();
+        let args = Args::parse_from([
+            "checkmate", "--job", "ci.json", "--job-timeout", "30", "--tags", "lint", "--print-effective",
+        ]);
+
+        let printed = serde_json::to_value(effective(job.clone(), &args).unwrap()).unwrap();
+        assert_eq!(printed["timeout"], 30);
+        let names: Vec<_> = printed["tasks"].as_array().unwrap().iter().map(|t| &t["Script"]["name"]).collect();
+        assert_eq!(names, ["lint"]);
+
+        // Without overrides the job prints as written
+        let args = Args::parse_from(["checkmate", "--job", "ci.json", "--print-effective"]);
+        let printed = serde_json::to_value(effective(job, &args).unwrap()).unwrap();
+        assert_eq!(printed["timeout"], 10);
+        assert_eq!(printed["tasks"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn no_terminal_to_ask_on_refuses_a_remote_job() {
+        let job: Job = serde_json::from_value(serde_json::json!({"name": "deploy", "tasks": [
+            {"Script": {"name": "restart", "destination": {"Remote": "ops@web1"},
+                "environment": "Current", "shell": "Bash", "script": "true"}},
+        ]}))
+        .unwrap();
+        let mut prompt = vec![];
+        let refused = confirmed(&job, None::<&[u8]>, &mut prompt).unwrap_err();
+        assert_eq!(refused.to_string(), "stdin is not a terminal, pass --yes");
+        assert!(prompt.is_empty());
+
+        let local = Job::new("lint", vec![Task::local("lint", "true")]);
+        assert!(confirmed(&local, None::<&[u8]>, io::sink()).unwrap());
+
+        let from_stdin = |flags: &[&str]| {
+            job_from_stdin(&Args::parse_from([&["checkmate"], flags].concat()))
+        };
+        assert!(from_stdin(&["--job", "-"]));
+        assert!(from_stdin(&["--stdin"]));
+        assert!(!from_stdin(&["--job", "deploy.json"]));
+    }
+}
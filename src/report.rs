@@ -0,0 +1,136 @@
+use crate::{JobRunner, Status};
+use std::fmt::Write;
+
+/// JUnit XML for a finished job: the job is the testsuite and every script a testcase,
+/// named after its task so serial and parallel steps stay grouped
+pub fn junit(runner: &JobRunner) -> String {
+    let mut cases = String::new();
+    let (mut tests, mut failures, mut skipped) = (0, 0, 0);
+    for jr in &runner.threads {
+        let thread = jr.thread.borrow();
+        let Ok(result) = &*thread else { continue };
+        let scripts = jr.task.scripts();
+        for (script, output) in scripts.iter().zip(result.outputs()) {
+            tests += 1;
+            // Only whole tasks are timed
+            let time = match scripts.len() {
+                1 => format!(" time=\"{:.3}\"", jr.duration().as_secs_f64()),
+                _ => String::new(),
+            };
+            let _ = write!(
+                cases,
+                "  <testcase classname=\"{}\" name=\"{}\"{time}",
+                escape(&jr.task.name()),
+                escape(&script.name)
+            );
+            let status = script.status(output);
+            let body = match (&status, output) {
+                (Status::Complete, _) => None,
+                (Status::Skipped, _) => {
+                    skipped += 1;
+                    Some("    <skipped/>\n".to_string())
+                }
+                (_, Ok(output)) => {
+                    failures += 1;
+                    Some(format!(
+                        "    <failure message=\"{}\">{}</failure>\n",
+                        escape(&status.to_string()),
+                        escape(&String::from_utf8_lossy(&output.stderr))
+                    ))
+                }
+                (_, Err(e)) => {
+                    failures += 1;
+                    Some(format!(
+                        "    <failure message=\"{}\">{}</failure>\n",
+                        escape(&status.to_string()),
+                        escape(&e.to_string())
+                    ))
+                }
+            };
+            match body {
+                Some(body) => {
+                    let _ = write!(cases, ">\n{body}  </testcase>\n");
+                }
+                None => cases.push_str("/>\n"),
+            }
+        }
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuite name=\"{}\" tests=\"{tests}\" failures=\"{failures}\" skipped=\"{skipped}\" time=\"{:.3}\">\n\
+         {cases}</testsuite>\n",
+        escape(&runner.job.name),
+        runner.summary().wall.as_secs_f64()
+    )
+}
+
+/// Escape text for XML attributes and content, dropping characters XML 1.0 can't hold
+fn escape(text: &str) -> String {
+    text.chars()
+        .filter(|&c| matches!(c, '\t' | '\n' | '\r') || c >= ' ')
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Job, Script, Task};
+
+    fn finished(name: &str, tasks: Vec<Task>) -> JobRunner {
+        let runner = Job::new(name, tasks).run();
+        let _ = runner.wait_all();
+        runner
+    }
+
+    #[test]
+    fn junit_has_a_testcase_per_script_and_a_failure_per_failed_one() {
+        let runner = finished(
+            "ci & <nightly>",
+            vec![
+                Task::local("build", "true"),
+                Task::local("test", "echo 'x < y & \"q\"' >&2; exit 1"),
+                Task::Serial(vec![Script::new("lint", "true"), Script::new("fmt", "exit 2")]),
+            ],
+        );
+        let xml = junit(&runner);
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"), "{xml}");
+        assert!(
+            xml.contains(
+                "<testsuite name=\"ci &amp; &lt;nightly&gt;\" tests=\"4\" failures=\"2\" skipped=\"0\" time=\""
+            ),
+            "{xml}"
+        );
+        assert!(xml.ends_with("</testsuite>\n"), "{xml}");
+        assert_eq!(xml.matches("<testcase ").count(), 4, "{xml}");
+        assert_eq!(xml.matches("<failure ").count(), 2, "{xml}");
+        assert!(xml.contains("<testcase classname=\"build\" name=\"build\" time=\""), "{xml}");
+        assert!(
+            xml.contains("<failure message=\"Failed (exit 1)\">x &lt; y &amp; &quot;q&quot;\n</failure>"),
+            "{xml}"
+        );
+        // Steps are grouped under their task and untimed
+        assert!(xml.contains("<testcase classname=\"lint =&gt; fmt\" name=\"lint\"/>"), "{xml}");
+        assert!(
+            xml.contains("<testcase classname=\"lint =&gt; fmt\" name=\"fmt\">\n    <failure message=\"Failed (exit 2)\">"),
+            "{xml}"
+        );
+    }
+
+    #[test]
+    fn escape_replaces_markup_and_drops_control_characters() {
+        assert_eq!(
+            escape(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&apos;s&lt;/a&gt;"
+        );
+        assert_eq!(escape("bell\u{7} tab\tline\n"), "bell tab\tline\n");
+    }
+}
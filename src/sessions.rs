@@ -0,0 +1,156 @@
+use crate::{KnownHostsPolicy, Remote};
+use anyhow::Result;
+use openssh::Session;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+type Slot<T> = Arc<tokio::sync::Mutex<Option<Arc<T>>>>;
+
+/// One ssh session per remote, shared by every script running on it.
+/// Sessions are closed when the last clone of the pool is dropped.
+#[derive(Clone, Debug, Default)]
+pub struct SessionPool {
+    sessions: Pool<Session>,
+}
+
+impl SessionPool {
+    /// The open session for `remote`, connecting if there isn't one or it has died
+    pub async fn get(&self, remote: &Remote, known_hosts: &KnownHostsPolicy) -> Result<Arc<Session>> {
+        self.sessions
+            .get(
+                key(remote, known_hosts),
+                |session| async move { session.check().await.is_ok() },
+                || async {
+                    tracing::debug!("Connecting to {remote}");
+                    remote.connect(known_hosts).await
+                },
+            )
+            .await
+    }
+}
+
+/// Remotes on the same host can still differ in user, key or options
+fn key(remote: &Remote, known_hosts: &KnownHostsPolicy) -> String {
+    format!("{remote:?} {known_hosts:?}")
+}
+
+/// Values shared by key, made once and made again when they stop working
+#[derive(Debug)]
+struct Pool<T> {
+    slots: Arc<Mutex<HashMap<String, Slot<T>>>>,
+}
+
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self {
+            slots: Arc::default(),
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    /// The value for `key` while `alive` says it still works, otherwise a new one from `make`
+    async fn get<Alive, Made>(
+        &self,
+        key: String,
+        alive: impl FnOnce(Arc<T>) -> Alive,
+        make: impl FnOnce() -> Made,
+    ) -> Result<Arc<T>>
+    where
+        Alive: Future<Output = bool>,
+        Made: Future<Output = Result<T>>,
+    {
+        let slot = self
+            .slots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key)
+            .or_default()
+            .clone();
+
+        // Only one caller makes the value, the rest wait for it
+        let mut slot = slot.lock().await;
+        if let Some(value) = &*slot {
+            if alive(value.clone()).await {
+                return Ok(value.clone());
+            }
+        }
+        let value = Arc::new(make().await?);
+        *slot = Some(value.clone());
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn callers_share_one_value_per_key() {
+        let pool: Pool<usize> = Pool::default();
+        let made = AtomicUsize::new(0);
+        let get = |key: &str, alive: bool| {
+            pool.get(
+                key.into(),
+                move |_| async move { alive },
+                || async {
+                    // Long enough for concurrent callers to queue behind the first
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok(made.fetch_add(1, Ordering::SeqCst))
+                },
+            )
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let (a, b) = tokio::join!(get("web", true), get("web", true));
+            assert!(Arc::ptr_eq(&a.unwrap(), &b.unwrap()));
+            assert_eq!(made.load(Ordering::SeqCst), 1);
+
+            assert_eq!(*get("db", true).await.unwrap(), 1);
+            assert_eq!(*get("web", true).await.unwrap(), 0);
+            // A dead value is replaced
+            assert_eq!(*get("web", false).await.unwrap(), 2);
+            assert_eq!(*get("web", true).await.unwrap(), 2);
+        });
+    }
+
+    #[test]
+    fn failed_connections_are_retried() {
+        let pool: Pool<usize> = Pool::default();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let failed = pool
+                .get("web".into(), |_| async { true }, || async { Err(anyhow::anyhow!("refused")) })
+                .await;
+            assert_eq!(failed.unwrap_err().to_string(), "refused");
+            let retried = pool.get("web".into(), |_| async { true }, || async { Ok(7) }).await;
+            assert_eq!(*retried.unwrap(), 7);
+        });
+    }
+
+    #[test]
+    fn remotes_differing_in_settings_get_their_own_session() {
+        let strict = KnownHostsPolicy::Strict;
+        let web: Remote = "deploy@web".into();
+        assert_eq!(key(&web, &strict), key(&"deploy@web".into(), &strict));
+        assert_ne!(key(&web, &strict), key(&"admin@web".into(), &strict));
+        assert_ne!(key(&web, &strict), key(&web, &KnownHostsPolicy::Accept));
+        let other_port = Remote {
+            port: Some(2222),
+            ..web.clone()
+        };
+        assert_ne!(key(&web, &strict), key(&other_port, &strict));
+    }
+}
@@ -0,0 +1,364 @@
+use anyhow::{anyhow, Result};
+use checkmate::{JobRunner, Progress};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// How often the watched runner is checked for new output and status changes
+const POLL: Duration = Duration::from_millis(100);
+/// A client that can't take a message this quickly is dropped rather than stall the rest
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time a client has to finish the websocket handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves a websocket that sends each task's output and status changes as JSON messages:
+///
+/// ```text
+/// {"event":"status","task":"build","status":"In progress"}
+/// {"event":"output","task":"build","script":"build","stream":"stdout","data":"ok\n"}
+/// {"event":"done","complete":1,"failed":0}
+/// ```
+///
+/// Clients that connect late are first sent each task's output so far and its status.
+pub struct Broadcaster {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    poller: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    runner: Mutex<Option<JobRunner>>,
+    clients: Mutex<Clients>,
+    stop: AtomicBool,
+}
+
+#[derive(Default)]
+struct Clients {
+    /// Connected since the last poll, still to be caught up
+    pending: Vec<WebSocket<TcpStream>>,
+    connected: Vec<WebSocket<TcpStream>>,
+    /// What has been sent of each task, by the address of its progress buffers so
+    /// tasks with the same name and a rerun's new tasks are told apart
+    tasks: HashMap<usize, Seen>,
+}
+
+/// What has been sent of a task
+struct Seen {
+    /// Held so the address keying it isn't reused by another task
+    _progress: Arc<Vec<Progress>>,
+    status: String,
+    /// Bytes sent of each script's stdout and stderr
+    streamed: Vec<[usize; 2]>,
+}
+
+impl Broadcaster {
+    /// Listen on `port` of localhost, use an ssh tunnel to watch from elsewhere.
+    /// Port 0 picks a free one, see [`Broadcaster::addr`].
+    pub fn bind(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| anyhow!("Failed to listen on websocket port {port}: {e}"))?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared::default());
+
+        let accepting = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let accepting = accepting.clone();
+                // Off the accept loop, so a client that stalls its handshake doesn't hold up the rest
+                thread::spawn(move || {
+                    let socket = stream.map_err(anyhow::Error::from).and_then(|stream| {
+                        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+                        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                        Ok(tungstenite::accept(stream)?)
+                    });
+                    match socket {
+                        Ok(socket) => lock(&accepting.clients).pending.push(socket),
+                        Err(e) => tracing::warn!("Websocket connection failed: {e}"),
+                    }
+                });
+            }
+        });
+
+        let polling = shared.clone();
+        let poller = thread::spawn(move || {
+            while !polling.stop.load(Ordering::SeqCst) {
+                polling.poll();
+                thread::sleep(POLL);
+            }
+        });
+
+        Ok(Self {
+            addr,
+            shared,
+            poller: Some(poller),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Send events for `runner` from now on, in place of the runner watched before
+    pub fn watch(&self, runner: &JobRunner) {
+        *lock(&self.shared.runner) = Some(runner.clone());
+    }
+
+    /// Send what's left of the watched runner and a last `done` message, then close every client
+    pub fn close(mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(poller) = self.poller.take() {
+            let _ = poller.join();
+        }
+        self.shared.poll();
+        let runner = lock(&self.shared.runner).clone();
+        let mut clients = lock(&self.shared.clients);
+        if let Some(runner) = runner {
+            let (complete, failed, _) = runner.counts();
+            let done = json!({"event": "done", "complete": complete, "failed": failed}).to_string();
+            let snapshot = clients.snapshot(&runner);
+            clients.broadcast(snapshot, &[done]);
+        }
+        for client in &mut clients.connected {
+            let _ = client.close(None);
+            let _ = client.flush();
+        }
+    }
+}
+
+impl Shared {
+    fn poll(&self) {
+        let Some(runner) = lock(&self.runner).clone() else {
+            return;
+        };
+        let mut clients = lock(&self.clients);
+        let snapshot = clients.snapshot(&runner);
+        let messages = clients.changes(&runner);
+        clients.broadcast(snapshot, &messages);
+    }
+}
+
+impl Clients {
+    /// Output that arrived and statuses that changed since the last call, in job order
+    fn changes(&mut self, runner: &JobRunner) -> Vec<String> {
+        let mut messages = vec![];
+        for jr in &runner.threads {
+            let task = jr.task.name();
+            let seen = self.tasks.entry(Arc::as_ptr(&jr.progress) as usize).or_insert_with(|| Seen {
+                _progress: jr.progress.clone(),
+                status: String::new(),
+                streamed: vec![[0, 0]; jr.progress.len()],
+            });
+
+            // Output first, so a task's output has all been sent by its final status
+            for ((script, progress), streamed) in
+                jr.task.scripts().iter().zip(jr.progress.iter()).zip(&mut seen.streamed)
+            {
+                for (i, stream) in ["stdout", "stderr"].into_iter().enumerate() {
+                    if let Some(data) = unsent(progress, i, &mut streamed[i]) {
+                        messages.push(output(&task, &script.name, stream, &data));
+                    }
+                }
+            }
+
+            let status = jr.status().to_string();
+            if seen.status != status {
+                messages.push(json!({"event": "status", "task": task, "status": status}).to_string());
+                seen.status = status;
+            }
+        }
+        messages
+    }
+
+    /// What has been sent of each task so far, for a client that just connected:
+    /// its output, at most `max_output_bytes` of each stream, then its status
+    fn snapshot(&self, runner: &JobRunner) -> Vec<String> {
+        if self.pending.is_empty() {
+            return vec![];
+        }
+        let mut messages = vec![];
+        for jr in &runner.threads {
+            let Some(seen) = self.tasks.get(&(Arc::as_ptr(&jr.progress) as usize)) else {
+                continue;
+            };
+            let task = jr.task.name();
+            for ((script, progress), streamed) in
+                jr.task.scripts().iter().zip(jr.progress.iter()).zip(&seen.streamed)
+            {
+                for (i, stream) in ["stdout", "stderr"].into_iter().enumerate() {
+                    let buffer = lock([&progress.stdout, &progress.stderr][i]);
+                    let sent = &buffer[..streamed[i].min(buffer.len())];
+                    if !sent.is_empty() {
+                        messages.push(output(&task, &script.name, stream, &String::from_utf8_lossy(sent)));
+                    }
+                }
+            }
+            if !seen.status.is_empty() {
+                messages.push(json!({"event": "status", "task": task, "status": seen.status}).to_string());
+            }
+        }
+        messages
+    }
+
+    /// Send `messages` to every client, the ones that just connected get `snapshot` first
+    fn broadcast(&mut self, snapshot: Vec<String>, messages: &[String]) {
+        self.connected.retain_mut(|client| send(client, messages));
+        let pending: Vec<_> = self.pending.drain(..).collect();
+        for mut client in pending {
+            if send(&mut client, &snapshot) && send(&mut client, messages) {
+                self.connected.push(client);
+            }
+        }
+    }
+}
+
+fn output(task: &str, script: &str, stream: &str, data: &str) -> String {
+    json!({"event": "output", "task": task, "script": script, "stream": stream, "data": data}).to_string()
+}
+
+/// Output of one of the script's streams past `streamed`, moving it along.
+/// A buffer shorter than what was sent was cleared for a retry or cut to `max_output_bytes`,
+/// and is sent again from its start. A character split between reads waits for its end.
+fn unsent(progress: &Progress, stream: usize, streamed: &mut usize) -> Option<String> {
+    let buffer = [&progress.stdout, &progress.stderr][stream]
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if buffer.len() < *streamed {
+        *streamed = 0;
+    }
+    let new = &buffer[*streamed..];
+    let complete = match std::str::from_utf8(new) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => new.len(),
+    };
+    if complete == 0 {
+        return None;
+    }
+    *streamed += complete;
+    Some(String::from_utf8_lossy(&new[..complete]).into_owned())
+}
+
+/// False once the client is gone or too slow
+fn send(client: &mut WebSocket<TcpStream>, messages: &[String]) -> bool {
+    for message in messages {
+        if client.write(Message::Text(message.clone())).is_err() {
+            return false;
+        }
+    }
+    client.flush().is_ok()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checkmate::{Job, Task};
+    use serde_json::Value;
+
+    fn connect(broadcaster: &Broadcaster) -> WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>> {
+        tungstenite::connect(format!("ws://{}", broadcaster.addr())).unwrap().0
+    }
+
+    fn wait(runner: &JobRunner) {
+        while runner.finished() < runner.threads.len() {
+            thread::sleep(POLL);
+        }
+    }
+
+    /// Every message until the server closes
+    fn events(mut client: WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>) -> Vec<Value> {
+        let mut events = vec![];
+        while let Ok(Message::Text(text)) = client.read() {
+            events.push(serde_json::from_str(&text).unwrap());
+        }
+        events
+    }
+
+    fn stdout(events: &[Value], task: &str) -> Vec<String> {
+        events
+            .iter()
+            .filter(|e| e["event"] == "output" && e["stream"] == "stdout" && e["task"] == task)
+            .map(|e| e["data"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn client_receives_task_events() {
+        let broadcaster = Broadcaster::bind(0).unwrap();
+        let client = connect(&broadcaster);
+
+        let runner = Job::new("ws", vec![Task::local("greet", "sleep 0.3; echo hello")]).run();
+        broadcaster.watch(&runner);
+        wait(&runner);
+        broadcaster.close();
+
+        let events = events(client);
+        let statuses: Vec<&str> = events
+            .iter()
+            .filter(|e| e["event"] == "status")
+            .map(|e| e["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses.last(), Some(&"Complete"));
+        assert_eq!(stdout(&events, "greet").concat(), "hello\n");
+        assert_eq!(
+            events.last().unwrap(),
+            &json!({"event": "done", "complete": 1, "failed": 0})
+        );
+    }
+
+    #[test]
+    fn tasks_with_the_same_name_keep_their_own_output() {
+        let broadcaster = Broadcaster::bind(0).unwrap();
+        let client = connect(&broadcaster);
+
+        let runner = Job::new("ws", vec![Task::local("same", "echo one"), Task::local("same", "echo two")]).run();
+        broadcaster.watch(&runner);
+        wait(&runner);
+        broadcaster.close();
+
+        let mut output = stdout(&events(client), "same");
+        output.sort();
+        assert_eq!(output, vec!["one\n", "two\n"]);
+    }
+
+    #[test]
+    fn late_client_gets_a_snapshot_of_each_task() {
+        let broadcaster = Broadcaster::bind(0).unwrap();
+        let runner = Job::new("ws", vec![Task::local("greet", "echo hello")]).run();
+        broadcaster.watch(&runner);
+        wait(&runner);
+        // Let the poller send the task's events before anyone listens
+        thread::sleep(POLL * 3);
+
+        let client = connect(&broadcaster);
+        thread::sleep(POLL * 3);
+        broadcaster.close();
+
+        let events = events(client);
+        assert_eq!(stdout(&events, "greet"), vec!["hello\n"]);
+        let statuses: Vec<&Value> = events.iter().filter(|e| e["event"] == "status").collect();
+        assert_eq!(statuses, vec![&json!({"event": "status", "task": "greet", "status": "Complete"})]);
+    }
+
+    #[test]
+    fn stalled_handshake_does_not_block_other_clients() {
+        let broadcaster = Broadcaster::bind(0).unwrap();
+        let _stalled = TcpStream::connect(broadcaster.addr()).unwrap();
+        let client = connect(&broadcaster);
+
+        let runner = Job::new("ws", vec![Task::local("greet", "echo hello")]).run();
+        broadcaster.watch(&runner);
+        wait(&runner);
+        broadcaster.close();
+
+        assert_eq!(stdout(&events(client), "greet"), vec!["hello\n"]);
+    }
+}